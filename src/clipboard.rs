@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use copypasta::{ClipboardContext, ClipboardProvider};
+#[cfg(all(unix, not(target_os = "macos")))]
+use copypasta::x11_clipboard::{Primary, X11ClipboardContext};
+use neovim_lib::{Neovim, NeovimApi, RequestHandler};
+use rmpv::Value;
+
+use crate::settings::channel_id;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Selection {
+    Clipboard,
+    Primary
+}
+
+impl Selection {
+    fn from_register(register: &str) -> Option<Selection> {
+        match register {
+            "+" => Some(Selection::Clipboard),
+            "*" => Some(Selection::Primary),
+            _ => None
+        }
+    }
+}
+
+pub trait ClipboardBackend: Send {
+    fn get_contents(&mut self, selection: Selection) -> Result<String, String>;
+    fn set_contents(&mut self, selection: Selection, contents: String) -> Result<(), String>;
+}
+
+// The system clipboard. Only x11 has a separate primary selection, everywhere else the star
+// register shares the regular clipboard.
+pub struct NativeClipboard {
+    clipboard: ClipboardContext,
+    #[cfg(all(unix, not(target_os = "macos")))]
+    primary: X11ClipboardContext<Primary>
+}
+
+impl NativeClipboard {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    pub fn new() -> Result<NativeClipboard, String> {
+        Ok(NativeClipboard {
+            clipboard: ClipboardContext::new().map_err(|error| error.to_string())?,
+            primary: X11ClipboardContext::<Primary>::new().map_err(|error| error.to_string())?
+        })
+    }
+
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    pub fn new() -> Result<NativeClipboard, String> {
+        Ok(NativeClipboard {
+            clipboard: ClipboardContext::new().map_err(|error| error.to_string())?
+        })
+    }
+
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+impl ClipboardBackend for NativeClipboard {
+    fn get_contents(&mut self, selection: Selection) -> Result<String, String> {
+        let contents = match selection {
+            Selection::Clipboard => self.clipboard.get_contents(),
+            Selection::Primary => self.primary.get_contents()
+        };
+        contents.map_err(|error| error.to_string())
+    }
+
+    fn set_contents(&mut self, selection: Selection, contents: String) -> Result<(), String> {
+        let result = match selection {
+            Selection::Clipboard => self.clipboard.set_contents(contents),
+            Selection::Primary => self.primary.set_contents(contents)
+        };
+        result.map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+impl ClipboardBackend for NativeClipboard {
+    fn get_contents(&mut self, _selection: Selection) -> Result<String, String> {
+        self.clipboard.get_contents().map_err(|error| error.to_string())
+    }
+
+    fn set_contents(&mut self, _selection: Selection, contents: String) -> Result<(), String> {
+        self.clipboard.set_contents(contents).map_err(|error| error.to_string())
+    }
+}
+
+// Keeps the selections in memory for testing the provider without touching the system clipboard
+#[cfg(test)]
+pub struct MemoryClipboard {
+    contents: HashMap<Selection, String>
+}
+
+#[cfg(test)]
+impl MemoryClipboard {
+    pub fn new() -> MemoryClipboard {
+        MemoryClipboard {
+            contents: HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+impl ClipboardBackend for MemoryClipboard {
+    fn get_contents(&mut self, selection: Selection) -> Result<String, String> {
+        Ok(self.contents.get(&selection).cloned().unwrap_or_default())
+    }
+
+    fn set_contents(&mut self, selection: Selection, contents: String) -> Result<(), String> {
+        self.contents.insert(selection, contents);
+        Ok(())
+    }
+}
+
+pub type SharedClipboard = Arc<Mutex<Box<dyn ClipboardBackend>>>;
+
+// Without a system clipboard, for example on wayland without x11, the registers are left to
+// neovim's own providers such as wl-copy
+pub fn create_clipboard() -> Option<SharedClipboard> {
+    match NativeClipboard::new() {
+        Ok(clipboard) => {
+            let backend: Box<dyn ClipboardBackend> = Box::new(clipboard);
+            Some(Arc::new(Mutex::new(backend)))
+        },
+        Err(error) => {
+            println!("Could not open the system clipboard, leaving it to neovim: {}", error);
+            None
+        }
+    }
+}
+
+// Answers the clipboard_copy and clipboard_paste rpcrequests made by the g:clipboard provider.
+// The register type of the last copy is remembered so that a linewise or blockwise yank pastes
+// the same way as long as nothing else changed the clipboard in between.
+pub struct ClipboardHandler {
+    clipboard: SharedClipboard,
+    register_types: HashMap<Selection, (String, String)>
+}
+
+impl ClipboardHandler {
+    pub fn new(clipboard: SharedClipboard) -> ClipboardHandler {
+        ClipboardHandler {
+            clipboard,
+            register_types: HashMap::new()
+        }
+    }
+
+    fn copy(&mut self, arguments: Vec<Value>) -> Result<Value, Value> {
+        if let [Value::Array(lines), register_type, register] = arguments.as_slice() {
+            let selection = register.as_str().and_then(Selection::from_register)
+                .ok_or_else(|| Value::from("Invalid register"))?;
+            let contents = lines.iter()
+                .map(|line| line.as_str().unwrap_or(""))
+                .collect::<Vec<&str>>()
+                .join("\n");
+            let register_type = register_type.as_str().unwrap_or("v").to_string();
+
+            self.clipboard.lock().unwrap().set_contents(selection, contents.clone())?;
+            self.register_types.insert(selection, (contents, register_type));
+            Ok(Value::Nil)
+        } else {
+            Err(Value::from("Invalid clipboard_copy arguments"))
+        }
+    }
+
+    fn paste(&mut self, arguments: Vec<Value>) -> Result<Value, Value> {
+        if let [register] = arguments.as_slice() {
+            let selection = register.as_str().and_then(Selection::from_register)
+                .ok_or_else(|| Value::from("Invalid register"))?;
+            let contents = self.clipboard.lock().unwrap().get_contents(selection)?;
+            let register_type = match self.register_types.get(&selection) {
+                Some((copied, register_type)) if copied == &contents => register_type.clone(),
+                _ => "v".to_string()
+            };
+
+            let lines = contents
+                .split('\n')
+                .map(|line| Value::from(line.trim_end_matches('\r')))
+                .collect::<Vec<Value>>();
+            Ok(Value::Array(vec![Value::Array(lines), Value::from(register_type)]))
+        } else {
+            Err(Value::from("Invalid clipboard_paste arguments"))
+        }
+    }
+}
+
+impl RequestHandler for ClipboardHandler {
+    fn handle_request(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, Value> {
+        match name {
+            "clipboard_copy" => self.copy(arguments),
+            "clipboard_paste" => self.paste(arguments),
+            _ => Err(Value::from(format!("Unknown request {}", name)))
+        }
+    }
+}
+
+// Cuts the text covered by a visual selection out of the lines it spans. Positions are the
+// (line, byte column) pairs of getpos() and the lines start at the first selected line.
+fn selected_text(lines: &[String], start: (u64, u64), end: (u64, u64), mode: &str) -> String {
+    let (start, end) = if start <= end { (start, end) } else { (end, start) };
+    let (start_column, end_column) = (start.1.saturating_sub(1) as usize, end.1.saturating_sub(1) as usize);
+
+    // Columns point at the first byte of a character, so the end is extended to the next
+    // character boundary to include all of it
+    fn character_end(line: &str, column: usize) -> usize {
+        line.char_indices()
+            .map(|(index, _)| index)
+            .find(|index| *index > column)
+            .unwrap_or(line.len())
+    }
+
+    fn slice(line: &str, from: usize, to: usize) -> &str {
+        let from = from.min(line.len());
+        let to = to.min(line.len()).max(from);
+        line.get(from..to).unwrap_or("")
+    }
+
+    let last_index = lines.len().saturating_sub(1);
+    let selected = match mode {
+        "V" => lines.to_vec(),
+        "\u{16}" => {
+            let left = start_column.min(end_column);
+            let right = start_column.max(end_column);
+            lines.iter()
+                .map(|line| slice(line, left, character_end(line, right)).to_string())
+                .collect()
+        },
+        _ => lines.iter().enumerate()
+            .map(|(index, line)| {
+                let from = if index == 0 { start_column } else { 0 };
+                let to = if index == last_index { character_end(line, end_column) } else { line.len() };
+                slice(line, from, to).to_string()
+            })
+            .collect()
+    };
+    selected.join("\n")
+}
+
+// Handles the selection_changed notification sent while g:neovide_copy_on_select is set. Its
+// arguments are the selected lines, the start and end positions and the visual mode.
+pub fn handle_selection_notification(clipboard: &SharedClipboard, arguments: Vec<Value>) {
+    let parse_position = |position: &Value| -> Option<(u64, u64)> {
+        match position {
+            Value::Array(position) => Some((position.get(1)?.as_u64()?, position.get(2)?.as_u64()?)),
+            _ => None
+        }
+    };
+
+    if let [Value::Array(lines), start, end, mode] = arguments.as_slice() {
+        let lines = lines.iter()
+            .map(|line| line.as_str().unwrap_or("").to_string())
+            .collect::<Vec<String>>();
+        if let (Some(start), Some(end), Some(mode)) = (parse_position(start), parse_position(end), mode.as_str()) {
+            let text = selected_text(&lines, start, end, mode);
+            if let Err(error) = clipboard.lock().unwrap().set_contents(Selection::Primary, text) {
+                println!("Could not set the primary selection: {}", error);
+            }
+            return;
+        }
+    }
+    println!("Invalid selection_changed notification: {:?}", arguments);
+}
+
+// Installs neovide as the clipboard provider unless the user configured one already or turned
+// it off with g:neovide_clipboard_provider = v:false. Neovim only sources the user's config once
+// the ui attaches, so both are checked on VimEnter rather than here.
+pub fn setup_clipboard_provider(nvim: &mut Neovim) {
+    let channel = match channel_id(nvim) {
+        Some(channel) => channel,
+        None => {
+            println!("Could not find our channel, leaving the clipboard to neovim");
+            return;
+        }
+    };
+
+    let copy = |register: &str| format!(
+        "'{register}': {{lines, regtype -> rpcrequest({channel}, 'clipboard_copy', lines, regtype, '{register}')}}",
+        register = register, channel = channel);
+    let paste = |register: &str| format!(
+        "'{register}': {{-> rpcrequest({channel}, 'clipboard_paste', '{register}')}}",
+        register = register, channel = channel);
+    // The provider may already have been loaded while sourcing the user's config, so it is
+    // reloaded to pick up g:clipboard
+    let provider = format!(
+        "autocmd VimEnter * ++once if get(g:, 'neovide_clipboard_provider', 1) && !exists('g:clipboard') | \
+         let g:clipboard = {{'name': 'neovide', 'copy': {{{}, {}}}, 'paste': {{{}, {}}}, 'cache_enabled': 0}} | \
+         unlet! g:loaded_clipboard_provider | runtime autoload/provider/clipboard.vim | \
+         endif",
+        copy("+"), copy("*"), paste("+"), paste("*"));
+
+    // The selection is sent while the cursor moves in visual mode. The text is cut out of the
+    // selected lines on our side.
+    let copy_on_select = format!(
+        "autocmd CursorMoved * if get(g:, 'neovide_copy_on_select') && mode() =~# \"^[vV\\x16]\" | \
+         call rpcnotify({}, 'selection_changed', getline(min([line('v'), line('.')]), max([line('v'), line('.')])), getpos('v'), getpos('.'), mode()) | \
+         endif",
+        channel);
+
+    for command in [provider.as_str(), copy_on_select.as_str()].iter() {
+        if let Err(error) = nvim.command(command) {
+            println!("Could not set up the clipboard provider: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_handler() -> (SharedClipboard, ClipboardHandler) {
+        let backend: Box<dyn ClipboardBackend> = Box::new(MemoryClipboard::new());
+        let clipboard = Arc::new(Mutex::new(backend));
+        (clipboard.clone(), ClipboardHandler::new(clipboard))
+    }
+
+    fn copy(handler: &mut ClipboardHandler, lines: &[&str], register_type: &str, register: &str) {
+        let lines = lines.iter().map(|line| Value::from(*line)).collect::<Vec<Value>>();
+        let arguments = vec![Value::Array(lines), Value::from(register_type), Value::from(register)];
+        assert_eq!(handler.handle_request("clipboard_copy", arguments), Ok(Value::Nil));
+    }
+
+    fn paste(handler: &mut ClipboardHandler, register: &str) -> (Vec<String>, String) {
+        match handler.handle_request("clipboard_paste", vec![Value::from(register)]) {
+            Ok(Value::Array(result)) => match result.as_slice() {
+                [Value::Array(lines), register_type] => (
+                    lines.iter().map(|line| line.as_str().unwrap().to_string()).collect(),
+                    register_type.as_str().unwrap().to_string()),
+                _ => panic!("Invalid paste result {:?}", result)
+            },
+            result => panic!("Invalid paste result {:?}", result)
+        }
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn register_type_round_trips() {
+        let (_, mut handler) = memory_handler();
+        for register_type in ["v", "V", "\u{16}3"].iter() {
+            copy(&mut handler, &["one", "two"], register_type, "+");
+            assert_eq!(paste(&mut handler, "+"), (lines(&["one", "two"]), register_type.to_string()));
+        }
+    }
+
+    #[test]
+    fn registers_use_their_own_selection() {
+        let (clipboard, mut handler) = memory_handler();
+        copy(&mut handler, &["clipboard"], "V", "+");
+        copy(&mut handler, &["primary"], "v", "*");
+        assert_eq!(clipboard.lock().unwrap().get_contents(Selection::Clipboard), Ok("clipboard".to_string()));
+        assert_eq!(clipboard.lock().unwrap().get_contents(Selection::Primary), Ok("primary".to_string()));
+        assert_eq!(paste(&mut handler, "+"), (lines(&["clipboard"]), "V".to_string()));
+        assert_eq!(paste(&mut handler, "*"), (lines(&["primary"]), "v".to_string()));
+    }
+
+    #[test]
+    fn changed_clipboard_pastes_characterwise() {
+        let (clipboard, mut handler) = memory_handler();
+        copy(&mut handler, &["one"], "V", "+");
+        clipboard.lock().unwrap().set_contents(Selection::Clipboard, "other".to_string()).unwrap();
+        assert_eq!(paste(&mut handler, "+"), (lines(&["other"]), "v".to_string()));
+    }
+
+    #[test]
+    fn crlf_line_endings_are_stripped() {
+        let (clipboard, mut handler) = memory_handler();
+        clipboard.lock().unwrap().set_contents(Selection::Clipboard, "one\r\ntwo\r\n".to_string()).unwrap();
+        assert_eq!(paste(&mut handler, "+"), (lines(&["one", "two", ""]), "v".to_string()));
+    }
+
+    #[test]
+    fn invalid_requests_are_errors() {
+        let (_, mut handler) = memory_handler();
+        assert!(handler.handle_request("clipboard_paste", vec![Value::from("a")]).is_err());
+        assert!(handler.handle_request("clipboard_paste", Vec::new()).is_err());
+        assert!(handler.handle_request("clipboard_copy", vec![Value::from("one")]).is_err());
+        assert!(handler.handle_request("clipboard_clear", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn characterwise_selection() {
+        let text = lines(&["hello world"]);
+        assert_eq!(selected_text(&text, (1, 1), (1, 5), "v"), "hello");
+        assert_eq!(selected_text(&text, (1, 5), (1, 1), "v"), "hello");
+
+        let text = lines(&["hello", "big world"]);
+        assert_eq!(selected_text(&text, (1, 3), (2, 3), "v"), "llo\nbig");
+        assert_eq!(selected_text(&text, (2, 3), (1, 3), "v"), "llo\nbig");
+    }
+
+    #[test]
+    fn linewise_selection() {
+        let text = lines(&["hello", "big world"]);
+        assert_eq!(selected_text(&text, (1, 3), (2, 2), "V"), "hello\nbig world");
+    }
+
+    #[test]
+    fn blockwise_selection() {
+        let text = lines(&["abcdef", "ghijkl"]);
+        assert_eq!(selected_text(&text, (1, 2), (2, 4), "\u{16}"), "bcd\nhij");
+        assert_eq!(selected_text(&text, (1, 4), (2, 2), "\u{16}"), "bcd\nhij");
+
+        let text = lines(&["abcdef", "ab"]);
+        assert_eq!(selected_text(&text, (1, 3), (2, 5), "\u{16}"), "cde\n");
+    }
+
+    #[test]
+    fn selection_ending_on_multibyte_character() {
+        // The end column points at the first byte of ñ
+        let text = lines(&["añb"]);
+        assert_eq!(selected_text(&text, (1, 1), (1, 2), "v"), "añ");
+        assert_eq!(selected_text(&text, (1, 2), (1, 2), "\u{16}"), "ñ");
+    }
+
+    #[test]
+    fn selection_to_end_of_line() {
+        // getpos() reports a huge column after $ in visual mode
+        let end_of_line = 2147483647;
+        let text = lines(&["short", "longer line"]);
+        assert_eq!(selected_text(&text, (1, 2), (2, end_of_line), "\u{16}"), "hort\nonger line");
+        assert_eq!(selected_text(&text, (1, 2), (2, end_of_line), "v"), "hort\nlonger line");
+    }
+}
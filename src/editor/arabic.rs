@@ -0,0 +1,11 @@
+// Neovim applies 'arabicshape' and mirrors 'rightleft' windows itself before sending the grid,
+// so neither option is tracked here. All that is left to the gui is keeping the shaper from
+// reordering the cells it was sent.
+
+// Hebrew, Arabic, Syriac, Thaana and N'Ko plus the Hebrew and Arabic presentation forms
+pub fn is_right_to_left(text: &str) -> bool {
+    text.chars().any(|character| match character as u32 {
+        0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF => true,
+        _ => false
+    })
+}
@@ -43,7 +43,7 @@ impl CommandLine {
                 let mut commands = self.content.iter().map(|(style_id, text)| {
                     let command_width = text.len() * 2;
                     let style = defined_styles.get(style_id).map(|style| style.clone());
-                    let mut command = DrawCommand::new(text.clone(), (start_x, y), style);
+                    let mut command = DrawCommand::new(text.clone(), text.chars().count() as u64, (start_x, y), style);
                     command.scale = COMMAND_SCALE;
                     start_x = start_x + command_width as u64;
                     command
@@ -53,20 +53,18 @@ impl Cursor {
         }
     }
 
-    pub fn foreground(&self, default_colors: &Colors) -> Color4f {
-        if let Some(style) = &self.style {
-            style.colors.foreground.clone().unwrap_or(default_colors.background.clone().unwrap())
-        } else {
-            default_colors.background.clone().unwrap()
-        }
+    // Colors missing from the cursor highlight fall back to the colors of the cell under the
+    // cursor swapped, which is how neovim draws a cursor without a highlight group.
+    pub fn foreground(&self, cell_style: &Style, default_colors: &Colors) -> Color4f {
+        self.style.as_ref()
+            .and_then(|style| style.colors.foreground.clone())
+            .unwrap_or_else(|| cell_style.background(default_colors))
     }
 
-    pub fn background(&self, default_colors: &Colors) -> Color4f {
-        if let Some(style) = &self.style {
-            style.colors.background.clone().unwrap_or(default_colors.foreground.clone().unwrap())
-        } else {
-            default_colors.foreground.clone().unwrap()
-        }
+    pub fn background(&self, cell_style: &Style, default_colors: &Colors) -> Color4f {
+        self.style.as_ref()
+            .and_then(|style| style.colors.background.clone())
+            .unwrap_or_else(|| cell_style.foreground(default_colors))
     }
 
     pub fn change_mode(&mut self, mode_index: u64, styles: &HashMap<u64, Style>) {
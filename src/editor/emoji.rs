@@ -0,0 +1,63 @@
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+const TEXT_PRESENTATION_SELECTOR: char = '\u{FE0E}';
+
+fn is_skin_tone_modifier(character: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&character)
+}
+
+fn is_regional_indicator(character: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&character)
+}
+
+// Characters which default to emoji presentation. This covers the pictographic blocks rather
+// than every individual code point in the emoji data files.
+fn has_emoji_presentation(character: char) -> bool {
+    match character as u32 {
+        0x1F000..=0x1F02F | 0x1F0A0..=0x1F0FF => true,
+        0x1F300..=0x1F5FF => true,
+        0x1F600..=0x1F64F => true,
+        0x1F680..=0x1F6FF => true,
+        0x1F900..=0x1F9FF => true,
+        0x1FA70..=0x1FAFF => true,
+        0x231A..=0x231B | 0x23E9..=0x23EC | 0x23F0 | 0x23F3 => true,
+        0x25FD..=0x25FE | 0x2614..=0x2615 | 0x2648..=0x2653 => true,
+        0x267F | 0x2693 | 0x26A1 | 0x26AA..=0x26AB | 0x26BD..=0x26BE => true,
+        0x26C4..=0x26C5 | 0x26CE | 0x26D4 | 0x26EA | 0x26F2..=0x26F3 | 0x26F5 | 0x26FA | 0x26FD => true,
+        0x2705 | 0x270A..=0x270B | 0x2728 | 0x274C | 0x274E | 0x2753..=0x2755 | 0x2757 => true,
+        0x2795..=0x2797 | 0x27B0 | 0x27BF | 0x2B1B..=0x2B1C | 0x2B50 | 0x2B55 => true,
+        _ => false
+    }
+}
+
+// Returns true if the grapheme cluster stored in a grid cell should be drawn as a color emoji.
+// That is the case for characters with emoji presentation unless followed by the text
+// presentation selector, for anything explicitly requesting emoji presentation, and for
+// modifier, flag and zwj sequences.
+pub fn is_emoji_cluster(text: &str) -> bool {
+    let mut characters = text.chars();
+    let first = match characters.next() {
+        Some(first) => first,
+        None => return false
+    };
+
+    if text.contains(TEXT_PRESENTATION_SELECTOR) {
+        return false;
+    }
+
+    has_emoji_presentation(first)
+        || is_regional_indicator(first)
+        || text.contains(EMOJI_PRESENTATION_SELECTOR)
+        || (text.contains(ZERO_WIDTH_JOINER) && text.chars().any(has_emoji_presentation))
+        || characters.any(is_skin_tone_modifier)
+}
+
+// With 'emoji' set neovim gives every emoji cluster two cells. Without it only the emoji its own
+// width table lists as wide get a second cell, which arrives as an empty cell after them.
+pub fn emoji_cell_count(emoji_option: bool, followed_by_empty_cell: bool) -> u64 {
+    if emoji_option || followed_by_empty_cell {
+        2
+    } else {
+        1
+    }
+}
@@ -1,22 +1,31 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 use skulpin::skia_safe::colors;
 use skulpin::winit::window::Window;
 
+mod arabic;
 mod cursor;
+mod emoji;
 mod style;
 mod command_line;
 
 pub use cursor::{Cursor, CursorShape, CursorMode};
+pub use emoji::is_emoji_cluster;
+use emoji::emoji_cell_count;
 pub use style::{Colors, Style};
+use arabic::is_right_to_left;
 use command_line::CommandLine;
-use crate::events::{GridLineCell, RedrawEvent};
+use crate::events::{GridLineCell, GuiOption, RedrawEvent};
 
-pub type GridCell = Option<(char, Option<Style>)>;
+// Each cell holds the grapheme cluster neovim sent for it. The cell to the right of a double
+// width character holds an empty string.
+pub type GridCell = Option<(String, Option<Style>)>;
 
 #[derive(new, Debug, Clone)]
 pub struct DrawCommand {
     pub text: String,
+    pub cell_width: u64,
     pub grid_position: (u64, u64),
     pub style: Option<Style>,
     #[new(value = "1")]
@@ -26,7 +35,7 @@ pub struct DrawCommand {
 impl DrawCommand {
     pub fn set_coverage(&self, dirty: &mut Vec<Vec<bool>>) {
         let (left, top) = self.grid_position;
-        let text_width = self.text.chars().count() * self.scale as usize;
+        let text_width = self.cell_width as usize * self.scale as usize;
 
         for y in top..(top + self.scale as u64 - 1) {
             let row = &mut dirty[y as usize];
@@ -37,6 +46,41 @@ impl DrawCommand {
     }
 }
 
+// Emoji are drawn with a different font and right to left text has to be placed one cell at a
+// time so that the shaper can't reorder it, so both get draw commands of their own.
+fn draws_alone(text: &str) -> bool {
+    is_emoji_cluster(text) || is_right_to_left(text)
+}
+
+fn blend_cell(underlying: &GridCell, character: &str, overlay_style: &Style, default_colors: &Colors) -> (String, Option<Style>) {
+    let (underlying_character, underlying_style) = underlying.clone()
+        .unwrap_or((" ".to_string(), None));
+    let underlying_style = underlying_style.unwrap_or(Style::new(default_colors.clone()));
+    let through = character == " ";
+    let blended_style = overlay_style.blend_over(&underlying_style, through, default_colors);
+
+    if through {
+        (underlying_character, Some(blended_style))
+    } else {
+        (character.to_string(), Some(blended_style))
+    }
+}
+
+#[derive(new, Debug, Clone)]
+pub struct ScrollRegion {
+    pub top: u64,
+    pub bottom: u64,
+    pub left: u64,
+    pub right: u64,
+    pub rows: i64
+}
+
+impl ScrollRegion {
+    pub fn same_area(&self, other: &ScrollRegion) -> bool {
+        self.top == other.top && self.bottom == other.bottom && self.left == other.left && self.right == other.right
+    }
+}
+
 pub struct Editor {
     pub grid: Vec<Vec<GridCell>>,
     pub dirty: Vec<Vec<bool>>,
@@ -46,11 +90,19 @@ pub struct Editor {
 
     pub command_line: CommandLine,
     pub title: String,
+    pub icon: String,
     pub size: (u64, u64),
     pub cursor: Cursor,
     pub default_colors: Colors,
     pub defined_styles: HashMap<u64, Style>,
-    pub previous_style: Option<Style>
+    pub previous_style: Option<Style>,
+    pub scroll_regions: Vec<ScrollRegion>,
+    pub last_flush: Option<Instant>,
+    pub focused: bool,
+    pub file_hovering: bool,
+    pub redraw_event_count: u64,
+    pub emoji: bool,
+    underlay: HashMap<(u64, u64), GridCell>
 }
 
 impl Editor {
@@ -64,11 +116,19 @@ impl Editor {
 
             command_line: CommandLine::new(),
             title: "Neovide".to_string(),
+            icon: String::new(),
             cursor: Cursor::new(),
             size: (width, height),
             default_colors: Colors::new(Some(colors::WHITE), Some(colors::BLACK), Some(colors::GREY)),
             defined_styles: HashMap::new(),
-            previous_style: None
+            previous_style: None,
+            scroll_regions: Vec::new(),
+            last_flush: None,
+            focused: true,
+            file_hovering: false,
+            redraw_event_count: 0,
+            emoji: true,
+            underlay: HashMap::new()
         };
 
         editor.clear();
@@ -76,28 +136,68 @@ impl Editor {
     }
 
     pub fn handle_redraw_event(&mut self, event: RedrawEvent) {
+        self.redraw_event_count += 1;
         match event {
-            RedrawEvent::SetTitle { title } => self.title = title,
+            RedrawEvent::SetTitle { title } => {
+                self.title = title;
+                self.window.as_ref().map(|window| window.request_redraw());
+            },
+            RedrawEvent::SetIcon { icon } => {
+                self.icon = icon;
+                self.window.as_ref().map(|window| window.request_redraw());
+            },
             RedrawEvent::ModeInfoSet { cursor_modes } => self.cursor.mode_list = cursor_modes,
             RedrawEvent::ModeChange { mode_index } => self.cursor.change_mode(mode_index, &self.defined_styles),
             RedrawEvent::BusyStart => self.cursor.enabled = false,
             RedrawEvent::BusyStop => self.cursor.enabled = true,
-            RedrawEvent::Flush => { self.window.as_ref().map(|window| window.request_redraw()); },
+            RedrawEvent::Flush => {
+                self.last_flush = Some(Instant::now());
+                self.window.as_ref().map(|window| window.request_redraw());
+            },
             RedrawEvent::Resize { width, height, .. } => self.resize((width, height)),
             RedrawEvent::DefaultColorsSet { colors } => self.default_colors = colors,
             RedrawEvent::HighlightAttributesDefine { id, style } => { self.defined_styles.insert(id, style); },
             RedrawEvent::GridLine { row, column_start, cells, .. } => self.draw_grid_line(row, column_start, cells),
             RedrawEvent::Clear { .. } => self.clear(),
-            RedrawEvent::CursorGoto { row, column, .. } => self.cursor.position = (row, column),
+            RedrawEvent::CursorGoto { row, column, .. } => {
+                // The text under the cursor may be shaped with its own font features, so the
+                // cells the cursor leaves and enters are redrawn
+                self.set_cell_dirty(self.cursor.position);
+                self.cursor.position = (row, column);
+                self.set_cell_dirty(self.cursor.position);
+            },
             RedrawEvent::Scroll { top, bottom, left, right, rows, columns, .. } => self.scroll_region(top, bottom, left, right, rows, columns),
+            RedrawEvent::OptionSet { gui_option: GuiOption::Emoji(emoji) } => {
+                self.emoji = emoji;
+                self.redraw_all();
+            },
             event => self.command_line.handle_command_events(event)
         };
     }
 
+    // Number of cells the cluster at the given position covers. Emoji follow the 'emoji' option
+    // and everything else is double width when neovim put an empty cell after it.
+    pub fn cell_count(&self, (row, column): (u64, u64)) -> u64 {
+        let row = match self.grid.get(row as usize) {
+            Some(row) => row,
+            None => return 1
+        };
+        let followed_by_empty_cell = match row.get(column as usize + 1) {
+            Some(Some((text, _))) => text.is_empty(),
+            _ => false
+        };
+        match row.get(column as usize) {
+            Some(Some((text, _))) if is_emoji_cluster(text) => emoji_cell_count(self.emoji, followed_by_empty_cell),
+            _ if followed_by_empty_cell => 2,
+            _ => 1
+        }
+    }
+
     pub fn build_draw_commands(&mut self) -> (Vec<DrawCommand>, bool) {
         let mut draw_commands = Vec::new();
         for (row_index, row) in self.grid.iter().enumerate() {
             let mut command = None;
+            let mut command_cell_count = 1;
 
             fn add_command(commands_list: &mut Vec<DrawCommand>, command: Option<DrawCommand>) {
                 if let Some(command) = command {
@@ -112,20 +212,31 @@ impl Editor {
                 }
             }
 
-            fn add_character(command: &mut Option<DrawCommand>, character: &char, row_index: u64, col_index: u64, style: Option<Style>) {
+            fn add_character(command: &mut Option<DrawCommand>, character: &str, row_index: u64, col_index: u64, style: Option<Style>) {
                 match command {
-                    Some(command) => command.text.push(character.clone()),
+                    Some(command) => {
+                        command.text.push_str(character);
+                        command.cell_width = command.cell_width + 1;
+                    },
                     None => {
-                        command.replace(DrawCommand::new(character.to_string(), (col_index, row_index), style));
+                        command.replace(DrawCommand::new(character.to_string(), 1, (col_index, row_index), style));
                     }
                 }
             }
 
             for (col_index, cell) in row.iter().enumerate() {
                 if let Some((character, new_style)) = cell {
-                    if !command_matches(&command, &new_style) {
+                    // Commands drawn alone also cover the empty cell neovim allocates after wide
+                    // characters, as far as the cell count of the cluster they start with.
+                    let command_draws_alone = command.as_ref()
+                        .map(|command| draws_alone(&command.text))
+                        .unwrap_or(false);
+                    let continues_command = command_draws_alone && character.is_empty()
+                        && command.as_ref().map(|command| command.cell_width < command_cell_count).unwrap_or(false);
+                    if !continues_command && (command_draws_alone || draws_alone(character) || !command_matches(&command, &new_style)) {
                         add_command(&mut draw_commands, command);
                         command = None;
+                        command_cell_count = self.cell_count((row_index as u64, col_index as u64));
                     }
                     add_character(&mut command, &character, row_index as u64, col_index as u64, new_style.clone());
                 } else {
@@ -141,8 +252,8 @@ impl Editor {
             let (x, y) = command.grid_position;
             let dirty_row = &self.dirty[y as usize];
 
-            for char_index in 0..command.text.chars().count() {
-                if dirty_row[x as usize + char_index] {
+            for cell_index in x..(x + command.cell_width).min(dirty_row.len() as u64) {
+                if dirty_row[cell_index as usize] {
                     return true;
                 }
             }
@@ -168,22 +279,39 @@ impl Editor {
             None => self.previous_style.clone()
         };
 
-        let mut text = cell.text;
-        if let Some(times) = cell.repeat {
-            text = text.repeat(times as usize);
-        }
+        let text = cell.text;
+        let repeat = cell.repeat.unwrap_or(1);
 
+        let default_colors = &self.default_colors;
+        let underlay = &mut self.underlay;
         let row = self.grid.get_mut(row_index as usize).expect("Grid must have size greater than row_index");
         let dirty_row = &mut self.dirty[row_index as usize];
-        for (i, character) in text.chars().enumerate() {
+        for i in 0..repeat as usize {
+            let character = &text;
             let pointer_index = i + *column_pos as usize;
             if pointer_index < row.len() {
-                row[pointer_index] = Some((character, style.clone()));
+                let position = (row_index, pointer_index as u64);
+                // Cells neovim's compositor already blended for 'winblend' and 'pumblend' arrive
+                // with the blend consumed, so they are stored as opaque content and never
+                // blended a second time. Only positions holding a blended cell remember what is
+                // under it, so that redrawing the blended cell is computed against the content
+                // neovim drew there rather than against the earlier blend. Anywhere else the grid
+                // itself already holds the latest opaque content.
+                row[pointer_index] = match &style {
+                    Some(overlay_style) if overlay_style.blend > 0 => {
+                        let underlying = underlay.entry(position).or_insert_with(|| row[pointer_index].clone());
+                        Some(blend_cell(underlying, character, overlay_style, default_colors))
+                    },
+                    _ => {
+                        underlay.remove(&position);
+                        Some((character.clone(), style.clone()))
+                    }
+                };
                 dirty_row[pointer_index] = true;
             }
         }
 
-        *column_pos = *column_pos + text.chars().count() as u64;
+        *column_pos = *column_pos + repeat;
         self.previous_style = style;
     }
 
@@ -199,6 +327,7 @@ impl Editor {
     }
 
     fn scroll_region(&mut self, top: u64, bot: u64, left: u64, right: u64, rows: i64, cols: i64) {
+        let (original_top, original_bot, original_left, original_right) = (top, bot, left, right);
         let (top, bot) =  if rows > 0 {
             (top as i64 + rows, bot as i64)
         } else if rows < 0 {
@@ -228,6 +357,26 @@ impl Editor {
         let new_top = top as i64 - rows;
         let new_left = left as i64 - cols;
 
+        // The underlay of blended cells moves along with the content it sits under
+        if !self.underlay.is_empty() {
+            let (original_rows, original_columns) = (original_top..original_bot, original_left..original_right);
+            let in_region = |&(y, x): &(u64, u64)| original_rows.contains(&y) && original_columns.contains(&x);
+            let moved_positions: Vec<(u64, u64)> = self.underlay.keys().cloned().filter(in_region).collect();
+            let moved: Vec<_> = moved_positions.into_iter()
+                .filter_map(|position| self.underlay.remove_entry(&position))
+                .collect();
+            for ((y, x), cell) in moved {
+                let (y, x) = (y as i64 - rows, x as i64 - cols);
+                if y >= 0 && x >= 0 && in_region(&(y as u64, x as u64)) {
+                    self.underlay.insert((y as u64, x as u64), cell);
+                }
+            }
+        }
+
+        if rows != 0 {
+            self.scroll_regions.push(ScrollRegion::new(original_top, original_bot, original_left, original_right, rows));
+        }
+
         for (y, row_section) in region.into_iter().enumerate() {
             for (x, cell) in row_section.into_iter().enumerate() {
                 let y = new_top + y as i64;
@@ -244,6 +393,18 @@ impl Editor {
         }
     }
 
+    fn set_cell_dirty(&mut self, (row, column): (u64, u64)) {
+        if let Some(dirty) = self.dirty.get_mut(row as usize).and_then(|row| row.get_mut(column as usize)) {
+            *dirty = true;
+        }
+    }
+
+    pub fn redraw_all(&mut self) {
+        let (width, height) = self.size;
+        self.dirty = vec![vec![true; width as usize]; height as usize];
+        self.should_clear = true;
+    }
+
     fn resize(&mut self, new_size: (u64, u64)) {
         self.size = new_size;
         self.clear();
@@ -253,6 +414,8 @@ impl Editor {
         let (width, height) = self.size;
         self.grid = vec![vec![None; width as usize]; height as usize];
         self.dirty = vec![vec![true; width as usize]; height as usize];
+        self.underlay.clear();
+        self.scroll_regions.clear();
         self.should_clear = true;
     }
 }
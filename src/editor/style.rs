@@ -23,7 +23,19 @@ pub struct Style {
     #[new(default)]
     pub undercurl: bool,
     #[new(default)]
-    pub blend: u8
+    pub underdouble: bool,
+    #[new(default)]
+    pub underdotted: bool,
+    #[new(default)]
+    pub underdashed: bool,
+    #[new(default)]
+    pub altfont: bool,
+    #[new(default)]
+    pub nocombine: bool,
+    #[new(default)]
+    pub blend: u8,
+    #[new(default)]
+    pub highlight_names: Vec<String>
 }
 
 impl Style {
@@ -46,4 +58,65 @@ impl Style {
     pub fn special(&self, default_colors: &Colors) -> Color4f {
         self.colors.special.clone().unwrap_or(default_colors.special.clone().unwrap())
     }
+
+    // Combines this style with the style of the cell it is drawn on top of following
+    // hl_blend_attrs in neovim's highlight.c. When the overlaying cell is blank (`through`), the
+    // underlying character stays visible and its foreground is faded toward the overlay
+    // background. Otherwise the overlay character is kept and its foreground is only blended
+    // at half the ratio. The blend is consumed by the result.
+    pub fn blend_over(&self, underlying: &Style, through: bool, default_colors: &Colors) -> Style {
+        // Ratios are integer percentages in neovim, including the halved one
+        let ratio = self.blend.min(100) as f32 / 100.0;
+        let half_ratio = (self.blend.min(100) / 2) as f32 / 100.0;
+
+        let front_foreground = self.foreground(default_colors);
+        let front_background = self.background(default_colors);
+        let back_foreground = underlying.foreground(default_colors);
+        let back_background = underlying.background(default_colors);
+
+        let mut blended = if through {
+            let mut blended = underlying.clone();
+            blended.colors.foreground = Some(blend_colors(ratio, &back_foreground, &front_background));
+            blended.colors.special = if blended.underline || blended.undercurl {
+                Some(blend_colors(ratio, &underlying.special(default_colors), &front_background))
+            } else {
+                None
+            };
+            blended
+        } else {
+            let mut blended = self.clone();
+            if self.blend >= 50 {
+                blended.bold |= underlying.bold;
+                blended.italic |= underlying.italic;
+                blended.strikethrough |= underlying.strikethrough;
+                blended.underline |= underlying.underline;
+                blended.undercurl |= underlying.undercurl;
+                blended.underdouble |= underlying.underdouble;
+                blended.underdotted |= underlying.underdotted;
+                blended.underdashed |= underlying.underdashed;
+            }
+            blended.colors.foreground = Some(blend_colors(half_ratio, &back_foreground, &front_foreground));
+            blended.colors.special = if blended.underline || blended.undercurl {
+                Some(blend_colors(half_ratio, &back_background, &self.special(default_colors)))
+            } else {
+                None
+            };
+            blended
+        };
+
+        blended.colors.background = Some(blend_colors(ratio, &back_background, &front_background));
+        blended.reverse = false;
+        blended.blend = 0;
+        blended
+    }
+}
+
+fn blend_colors(ratio: f32, back: &Color4f, front: &Color4f) -> Color4f {
+    let mix = |back: f32, front: f32| back * ratio + front * (1.0 - ratio);
+    Color4f {
+        r: mix(back.r, front.r),
+        g: mix(back.g, front.g),
+        b: mix(back.b, front.b),
+        a: 1.0
+    }
 }
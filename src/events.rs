@@ -86,7 +86,6 @@ impl MessageKind {
 
 #[derive(Debug)]
 pub enum GuiOption {
-    AribicShape(bool),
     AmbiWidth(String),
     Emoji(bool),
     GuiFont(String),
@@ -102,6 +101,7 @@ pub enum GuiOption {
 #[derive(Debug)]
 pub enum RedrawEvent {
     SetTitle { title: String },
+    SetIcon { icon: String },
     ModeInfoSet { cursor_modes: Vec<CursorMode> },
     OptionSet { gui_option: GuiOption },
     ModeChange { mode_index: u64 },
@@ -201,6 +201,16 @@ fn parse_set_title(set_title_arguments: Vec<Value>) -> Result<RedrawEvent> {
     }
 }
 
+fn parse_set_icon(set_icon_arguments: Vec<Value>) -> Result<RedrawEvent> {
+    if let [icon] = set_icon_arguments.as_slice() {
+        Ok(RedrawEvent::SetIcon {
+            icon: parse_string(icon)?
+        })
+    } else {
+        Err(EventParseError::InvalidEventFormat)
+    }
+}
+
 fn parse_mode_info_set(mode_info_set_arguments: Vec<Value>) -> Result<RedrawEvent> {
     if let [_cursor_style_enabled, mode_info] = mode_info_set_arguments.as_slice() {
         let mode_info_values = parse_array(mode_info)?;
@@ -237,7 +247,6 @@ fn parse_option_set(option_set_arguments: Vec<Value>) -> Result<RedrawEvent> {
     if let [name, value] = option_set_arguments.as_slice() {
         Ok(RedrawEvent::OptionSet {
             gui_option: match parse_string(&name)?.as_ref() {
-                "arabicshape" => GuiOption::AribicShape(parse_bool(&value)?),
                 "ambiwidth" => GuiOption::AmbiWidth(parse_string(&value)?),
                 "emoji" => GuiOption::Emoji(parse_bool(&value)?),
                 "guifont" => GuiOption::GuiFont(parse_string(&value)?),
@@ -306,6 +315,11 @@ fn parse_style(style_map: &Value) -> Result<Style> {
                     ("strikethrough", Value::Boolean(strikethrough)) => style.strikethrough = *strikethrough,
                     ("underline", Value::Boolean(underline)) => style.underline = *underline,
                     ("undercurl", Value::Boolean(undercurl)) => style.undercurl = *undercurl,
+                    ("underdouble", Value::Boolean(underdouble)) => style.underdouble = *underdouble,
+                    ("underdotted", Value::Boolean(underdotted)) => style.underdotted = *underdotted,
+                    ("underdashed", Value::Boolean(underdashed)) => style.underdashed = *underdashed,
+                    ("altfont", Value::Boolean(altfont)) => style.altfont = *altfont,
+                    ("nocombine", Value::Boolean(nocombine)) => style.nocombine = *nocombine,
                     ("blend", Value::Integer(blend)) => style.blend = blend.as_u64().unwrap() as u8,
                     _ => println!("Ignored style attribute: {}", name)
                 }
@@ -319,11 +333,26 @@ fn parse_style(style_map: &Value) -> Result<Style> {
     }
 }
 
+// With ext_hlstate the info array lists the highlight groups that were combined into the
+// attribute, which lets settings refer to highlight groups by name.
+fn parse_highlight_names(info: &Value) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for item in parse_array(info)? {
+        for (key, value) in parse_map(&item)? {
+            if parse_string(&key)? == "hi_name" {
+                names.push(parse_string(&value)?);
+            }
+        }
+    }
+    Ok(names)
+}
+
 fn parse_hl_attr_define(hl_attr_define_arguments: Vec<Value>) -> Result<RedrawEvent> {
     if let [
-        id, attributes, _terminal_attributes, _info
+        id, attributes, _terminal_attributes, info
     ] = hl_attr_define_arguments.as_slice() {
-        let style = parse_style(attributes)?;
+        let mut style = parse_style(attributes)?;
+        style.highlight_names = parse_highlight_names(info)?;
         Ok(RedrawEvent::HighlightAttributesDefine { id: parse_u64(&id)?, style })
     } else {
         Err(EventParseError::InvalidEventFormat)
@@ -534,7 +563,7 @@ pub fn parse_redraw_event(event_value: Value) -> Result<Vec<RedrawEvent>> {
         let event_parameters = parse_array(&event)?;
         let possible_parsed_event = match event_name.clone().as_ref() {
             "set_title" => Some(parse_set_title(event_parameters)?),
-            "set_icon" => None, // Ignore set icon for now
+            "set_icon" => Some(parse_set_icon(event_parameters)?),
             "mode_info_set" => Some(parse_mode_info_set(event_parameters)?),
             "option_set" => Some(parse_option_set(event_parameters)?),
             "mode_change" => Some(parse_mode_change(event_parameters)?),
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use neovim_lib::{Neovim, NeovimApi};
+use rmpv::Value;
+
+use crate::settings::SETTINGS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DropAction {
+    Edit,
+    Tab,
+    Split,
+    VerticalSplit
+}
+
+impl DropAction {
+    // Read from g:neovide_drop_action, which may be "edit", "tab", "split" or "vsplit"
+    fn from_settings() -> DropAction {
+        match SETTINGS.get_string("drop_action").as_ref().map(|action| action.as_str()) {
+            Some("tab") => DropAction::Tab,
+            Some("split") => DropAction::Split,
+            Some("vsplit") => DropAction::VerticalSplit,
+            _ => DropAction::Edit
+        }
+    }
+
+    fn window_command(&self) -> Option<&'static str> {
+        match self {
+            DropAction::Edit => None,
+            DropAction::Tab => Some("tabnew"),
+            DropAction::Split => Some("new"),
+            DropAction::VerticalSplit => Some("vnew")
+        }
+    }
+}
+
+// Escaping is left to neovim so that the result matches what the command line expects on the
+// current platform.
+fn escape_path(nvim: &mut Neovim, path: &PathBuf) -> Option<String> {
+    let path = path.to_string_lossy().into_owned();
+    match nvim.call_function("fnameescape", vec![Value::from(path)]) {
+        Ok(Value::String(escaped)) => escaped.into_str(),
+        _ => None
+    }
+}
+
+// A single file is edited directly. Several files dropped together replace the argument list
+// so they can be stepped through with :next and :previous.
+pub fn open_dropped_files(nvim: &mut Neovim, files: &[PathBuf]) {
+    let escaped_files = files.iter()
+        .filter_map(|file| escape_path(nvim, file))
+        .collect::<Vec<String>>();
+    if escaped_files.is_empty() {
+        return;
+    }
+
+    let action = DropAction::from_settings();
+    let open_command = if escaped_files.len() == 1 { "edit" } else { "args" };
+    let command = match action.window_command() {
+        Some(window_command) => format!("{} | {} {}", window_command, open_command, escaped_files.join(" ")),
+        None => format!("{} {}", open_command, escaped_files.join(" "))
+    };
+
+    if let Err(error) = nvim.command(&command) {
+        println!("Could not open dropped files: {:?}", error);
+    }
+}
@@ -1,5 +1,7 @@
 use skulpin::winit::event::{KeyboardInput, ElementState, ModifiersState, VirtualKeyCode};
 
+use crate::settings::SETTINGS;
+
 fn parse_keycode(keycode: VirtualKeyCode) -> Option<(String, bool)> {
     match keycode {
         VirtualKeyCode::Key1 => Some(("1".to_string(), false)),
@@ -88,48 +90,75 @@ fn parse_keycode(keycode: VirtualKeyCode) -> Option<(String, bool)> {
         VirtualKeyCode::Semicolon => Some((";".to_string(), false)),
         VirtualKeyCode::Slash => Some(("/".to_string(), false)),
         VirtualKeyCode::Tab => Some(("Tab".to_string(), true)),
+        VirtualKeyCode::Numpad0 => Some(("k0".to_string(), true)),
+        VirtualKeyCode::Numpad1 => Some(("k1".to_string(), true)),
+        VirtualKeyCode::Numpad2 => Some(("k2".to_string(), true)),
+        VirtualKeyCode::Numpad3 => Some(("k3".to_string(), true)),
+        VirtualKeyCode::Numpad4 => Some(("k4".to_string(), true)),
+        VirtualKeyCode::Numpad5 => Some(("k5".to_string(), true)),
+        VirtualKeyCode::Numpad6 => Some(("k6".to_string(), true)),
+        VirtualKeyCode::Numpad7 => Some(("k7".to_string(), true)),
+        VirtualKeyCode::Numpad8 => Some(("k8".to_string(), true)),
+        VirtualKeyCode::Numpad9 => Some(("k9".to_string(), true)),
+        VirtualKeyCode::Add => Some(("kPlus".to_string(), true)),
+        VirtualKeyCode::Subtract => Some(("kMinus".to_string(), true)),
+        VirtualKeyCode::Multiply => Some(("kMultiply".to_string(), true)),
+        VirtualKeyCode::Divide => Some(("kDivide".to_string(), true)),
+        VirtualKeyCode::Decimal => Some(("kPoint".to_string(), true)),
+        VirtualKeyCode::NumpadComma => Some(("kComma".to_string(), true)),
+        VirtualKeyCode::NumpadEquals => Some(("kEqual".to_string(), true)),
+        VirtualKeyCode::NumpadEnter => Some(("kEnter".to_string(), true)),
+        // Neovim has no notation for Pause, PrintScreen, ScrollLock, CapsLock, NumLock, the
+        // menu key or the media keys, so those are not forwarded.
         _ => None
     }
 }
 
+// With num lock off the keypad reports the same key codes as the navigation block, so the
+// keypad versions are told apart by their scan codes. Linux reports the evdev codes of the
+// keypad on both X11 and wayland. Other platforms don't expose a reliable difference, so there
+// the keypad navigation keys are sent as their navigation block counterparts.
+#[cfg(target_os = "linux")]
+fn parse_keypad_navigation(scancode: u32, keycode: Option<VirtualKeyCode>) -> Option<String> {
+    // With num lock on the keypad types digits, which parse_keycode handles
+    match keycode {
+        Some(VirtualKeyCode::Numpad0) | Some(VirtualKeyCode::Numpad1) | Some(VirtualKeyCode::Numpad2)
+            | Some(VirtualKeyCode::Numpad3) | Some(VirtualKeyCode::Numpad4) | Some(VirtualKeyCode::Numpad5)
+            | Some(VirtualKeyCode::Numpad6) | Some(VirtualKeyCode::Numpad7) | Some(VirtualKeyCode::Numpad8)
+            | Some(VirtualKeyCode::Numpad9) | Some(VirtualKeyCode::Decimal) => return None,
+        _ => {}
+    }
+
+    match scancode {
+        71 => Some("kHome".to_string()),
+        72 => Some("kUp".to_string()),
+        73 => Some("kPageUp".to_string()),
+        75 => Some("kLeft".to_string()),
+        76 => Some("kOrigin".to_string()),
+        77 => Some("kRight".to_string()),
+        79 => Some("kEnd".to_string()),
+        80 => Some("kDown".to_string()),
+        81 => Some("kPageDown".to_string()),
+        82 => Some("kInsert".to_string()),
+        83 => Some("kDel".to_string()),
+        _ => None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn parse_keypad_navigation(_scancode: u32, _keycode: Option<VirtualKeyCode>) -> Option<String> {
+    None
+}
+
+// Prefixes every held modifier, shift included, in the order neovim prints them. Callers which
+// already sent the shifted character clear shift first, see chord_from_character.
 fn append_modifiers(modifiers: ModifiersState, keycode_text: String, special: bool) -> String {
     let mut result = keycode_text;
     let mut special = special;
 
     if modifiers.shift {
-        result = match result.as_ref() {
-            "1" => "!".to_string(),
-            "2" => "@".to_string(),
-            "3" => "#".to_string(),
-            "4" => "$".to_string(),
-            "5" => "%".to_string(),
-            "6" => "^".to_string(),
-            "7" => "&".to_string(),
-            "8" => "*".to_string(),
-            "9" => "(".to_string(),
-            "0" => ")".to_string(),
-            "'" => "\"".to_string(),
-            "Bslash" => {
-                special = false;
-                "|".to_string()
-            },
-            "," => {
-                special = true;
-                "lt".to_string()
-            },
-            "=" => "+".to_string(),
-            "`" => "~".to_string(),
-            "[" => "{".to_string(),
-            "-" => "_".to_string(),
-            "." => ">".to_string(),
-            "]" => "}".to_string(),
-            ";" => ":".to_string(),
-            "/" => "?".to_string(),
-            other => {
-                special = true;
-                format!("S-{}", other)
-            }
-        };
+        special = true;
+        result = format!("S-{}", result);
     }
     if modifiers.ctrl {
         special = true;
@@ -151,20 +180,357 @@ fn append_modifiers(modifiers: ModifiersState, keycode_text: String, special: bo
     result
 }
 
-pub fn construct_keybinding_string(input: KeyboardInput) -> Option<String> {
-    match input {
-        KeyboardInput {
-            state: ElementState::Pressed,
-            virtual_keycode: Some(keycode),
-            modifiers,
-            ..
-        } => {
-            if let Some((keycode_text, special)) = parse_keycode(keycode) {
-                Some(append_modifiers(modifiers, keycode_text, special))
-            } else {
-                None
+// Keys which type text on their own. Without a modifier chord these are sent from the received
+// character instead so that the platform's keyboard layout, dead keys and input methods apply.
+fn is_text_key(keycode: VirtualKeyCode) -> bool {
+    match parse_keycode(keycode) {
+        Some((_, special)) => !special || keycode == VirtualKeyCode::Space || keycode == VirtualKeyCode::Backslash,
+        None => false
+    }
+}
+
+fn is_chord(modifiers: ModifiersState, option_as_meta: bool) -> bool {
+    // AltGr is reported as Ctrl+Alt on windows and is used to type characters like @ or € on
+    // many layouts, so it does not count as a chord there.
+    if cfg!(target_os = "windows") && modifiers.ctrl && modifiers.alt && !modifiers.logo {
+        return false;
+    }
+    // Option plays the same role on macOS unless g:neovide_macos_option_as_meta is set
+    if cfg!(target_os = "macos") && !option_as_meta && modifiers.alt && !modifiers.ctrl && !modifiers.logo {
+        return false;
+    }
+    modifiers.ctrl || modifiers.alt || modifiers.logo
+}
+
+fn escape_character(character: char) -> Option<String> {
+    match character {
+        '<' => Some("<lt>".to_string()),
+        // Control characters come from keys like Enter, Tab and Backspace or from Ctrl chords,
+        // all of which are sent from their key events.
+        character if character.is_control() => None,
+        character => Some(character.to_string())
+    }
+}
+
+// Ctrl chords are reported as ascii control characters. Mapping them back gives the character
+// the layout put on the key, such as ^ for Ctrl+Shift+6 on a US layout.
+fn uncontrol_character(character: char) -> Option<char> {
+    match character as u32 {
+        0x00 => Some('@'),
+        code @ 0x01..=0x1a => Some((b'a' + code as u8 - 1) as char),
+        0x1b => Some('['),
+        0x1c => Some('\\'),
+        0x1d => Some(']'),
+        0x1e => Some('^'),
+        0x1f => Some('_'),
+        _ if character.is_control() => None,
+        _ => Some(character)
+    }
+}
+
+// Builds a chord from the character typed while the modifiers were held. Letters keep an
+// explicit S- so that <C-S-x> and <M-S-x> are distinguishable from their unshifted versions,
+// while for other keys shift is already part of the character.
+fn chord_from_character(modifiers: ModifiersState, character: char) -> Option<String> {
+    let character = uncontrol_character(character)?;
+    let shift = modifiers.shift && character.is_alphabetic();
+    let text = match character {
+        '<' => "lt".to_string(),
+        '\\' => "Bslash".to_string(),
+        '|' => "Bar".to_string(),
+        ' ' => "Space".to_string(),
+        character if shift => character.to_lowercase().to_string(),
+        character => character.to_string()
+    };
+
+    let modifiers = ModifiersState { shift, ..modifiers };
+    Some(append_modifiers(modifiers, text, true))
+}
+
+// Text arrives through WindowEvent::ReceivedCharacter while special keys arrive through
+// WindowEvent::KeyboardInput. A key press which was already sent as a keybinding is usually
+// followed by a character for the same press, which is dropped.
+//
+// Chords on keys that type text wait for the character of the same press, so that the chord is
+// built from what the layout puts on the key rather than from its position. If no character
+// arrives before the event loop runs out of events, the key code is used instead.
+pub struct KeyboardHandler {
+    skip_next_character: bool,
+    pending_chord: Option<(ModifiersState, String)>
+}
+
+impl KeyboardHandler {
+    pub fn new() -> KeyboardHandler {
+        KeyboardHandler {
+            skip_next_character: false,
+            pending_chord: None
+        }
+    }
+
+    pub fn handle_keyboard_input(&mut self, input: KeyboardInput) -> Vec<String> {
+        self.handle_key(input, SETTINGS.get_bool("macos_option_as_meta", false))
+    }
+
+    fn handle_key(&mut self, input: KeyboardInput, option_as_meta: bool) -> Vec<String> {
+        let mut keybindings: Vec<String> = self.flush_pending_chord().into_iter().collect();
+
+        let (scancode, virtual_keycode, modifiers) = match input {
+            KeyboardInput {
+                state: ElementState::Pressed,
+                scancode,
+                virtual_keycode,
+                modifiers
+            } => (scancode, virtual_keycode, modifiers),
+            _ => return keybindings
+        };
+
+        self.skip_next_character = false;
+        if let Some(keycode_text) = parse_keypad_navigation(scancode, virtual_keycode) {
+            self.skip_next_character = true;
+            keybindings.push(append_modifiers(modifiers, keycode_text, true));
+            return keybindings;
+        }
+
+        let keycode = match virtual_keycode {
+            Some(keycode) => keycode,
+            None => return keybindings
+        };
+        if is_text_key(keycode) {
+            if is_chord(modifiers, option_as_meta) {
+                if let Some((keycode_text, _)) = parse_keycode(keycode) {
+                    self.pending_chord = Some((modifiers, keycode_text));
+                }
             }
-        },
-        _ => None
+        } else if let Some((keycode_text, special)) = parse_keycode(keycode) {
+            self.skip_next_character = true;
+            keybindings.push(append_modifiers(modifiers, keycode_text, special));
+        }
+
+        keybindings
+    }
+
+    pub fn handle_received_character(&mut self, character: char) -> Option<String> {
+        if let Some((modifiers, keycode_text)) = self.pending_chord.take() {
+            return chord_from_character(modifiers, character)
+                .or_else(|| Some(append_modifiers(modifiers, keycode_text, true)));
+        }
+
+        if self.skip_next_character {
+            self.skip_next_character = false;
+            return None;
+        }
+
+        escape_character(character)
+    }
+
+    // Called once the event loop has handled every queued event
+    pub fn flush_pending_chord(&mut self) -> Option<String> {
+        self.pending_chord.take()
+            .map(|(modifiers, keycode_text)| append_modifiers(modifiers, keycode_text, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Event {
+        Key(VirtualKeyCode, ModifiersState),
+        Character(char)
+    }
+    use Event::*;
+
+    const NONE: ModifiersState = ModifiersState { shift: false, ctrl: false, alt: false, logo: false };
+    const SHIFT: ModifiersState = ModifiersState { shift: true, ..NONE };
+    const CTRL: ModifiersState = ModifiersState { ctrl: true, ..NONE };
+    const ALT: ModifiersState = ModifiersState { alt: true, ..NONE };
+    const CTRL_SHIFT: ModifiersState = ModifiersState { ctrl: true, shift: true, ..NONE };
+    const ALT_SHIFT: ModifiersState = ModifiersState { alt: true, shift: true, ..NONE };
+
+    // Windows reports AltGr as Ctrl+Alt, X11 as a separate level shift which winit doesn't
+    // expose as a modifier.
+    #[cfg(target_os = "windows")]
+    const ALTGR: ModifiersState = ModifiersState { ctrl: true, alt: true, ..NONE };
+    #[cfg(not(target_os = "windows"))]
+    const ALTGR: ModifiersState = NONE;
+
+    // Feeds the events the way the window's event loop does, flushing once the queue is empty.
+    // Alt is always meta here, macos_option covers Option without g:neovide_macos_option_as_meta.
+    fn type_events(events: &[Event]) -> Vec<String> {
+        type_events_with_option(events, true)
+    }
+
+    fn type_events_with_option(events: &[Event], option_as_meta: bool) -> Vec<String> {
+        let mut handler = KeyboardHandler::new();
+        let mut keybindings = Vec::new();
+        for event in events {
+            match *event {
+                Key(keycode, modifiers) => {
+                    let input = KeyboardInput {
+                        scancode: 0,
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        modifiers
+                    };
+                    keybindings.extend(handler.handle_key(input, option_as_meta));
+                },
+                Character(character) => keybindings.extend(handler.handle_received_character(character))
+            }
+        }
+        keybindings.extend(handler.flush_pending_chord());
+        keybindings
+    }
+
+    const SPECIAL_KEYS: &[(VirtualKeyCode, &str)] = &[
+        (VirtualKeyCode::Numpad0, "k0"), (VirtualKeyCode::Numpad1, "k1"), (VirtualKeyCode::Numpad2, "k2"),
+        (VirtualKeyCode::Numpad3, "k3"), (VirtualKeyCode::Numpad4, "k4"), (VirtualKeyCode::Numpad5, "k5"),
+        (VirtualKeyCode::Numpad6, "k6"), (VirtualKeyCode::Numpad7, "k7"), (VirtualKeyCode::Numpad8, "k8"),
+        (VirtualKeyCode::Numpad9, "k9"), (VirtualKeyCode::Add, "kPlus"), (VirtualKeyCode::Subtract, "kMinus"),
+        (VirtualKeyCode::Multiply, "kMultiply"), (VirtualKeyCode::Divide, "kDivide"),
+        (VirtualKeyCode::Decimal, "kPoint"), (VirtualKeyCode::NumpadComma, "kComma"),
+        (VirtualKeyCode::NumpadEquals, "kEqual"), (VirtualKeyCode::NumpadEnter, "kEnter"),
+        (VirtualKeyCode::Insert, "Insert"), (VirtualKeyCode::Delete, "Delete"), (VirtualKeyCode::Home, "Home"),
+        (VirtualKeyCode::End, "End"), (VirtualKeyCode::PageUp, "PageUp"), (VirtualKeyCode::PageDown, "PageDown"),
+        (VirtualKeyCode::Left, "Left"), (VirtualKeyCode::Right, "Right"), (VirtualKeyCode::Up, "Up"),
+        (VirtualKeyCode::Down, "Down"),
+        (VirtualKeyCode::F1, "F1"), (VirtualKeyCode::F2, "F2"), (VirtualKeyCode::F3, "F3"), (VirtualKeyCode::F4, "F4"),
+        (VirtualKeyCode::F5, "F5"), (VirtualKeyCode::F6, "F6"), (VirtualKeyCode::F7, "F7"), (VirtualKeyCode::F8, "F8"),
+        (VirtualKeyCode::F9, "F9"), (VirtualKeyCode::F10, "F10"), (VirtualKeyCode::F11, "F11"),
+        (VirtualKeyCode::F12, "F12"), (VirtualKeyCode::F13, "F13"), (VirtualKeyCode::F14, "F14"),
+        (VirtualKeyCode::F15, "F15"), (VirtualKeyCode::F16, "F16"), (VirtualKeyCode::F17, "F17"),
+        (VirtualKeyCode::F18, "F18"), (VirtualKeyCode::F19, "F19"), (VirtualKeyCode::F20, "F20"),
+        (VirtualKeyCode::F21, "F21"), (VirtualKeyCode::F22, "F22"), (VirtualKeyCode::F23, "F23"),
+        (VirtualKeyCode::F24, "F24")
+    ];
+
+    // Every combination of shift, ctrl, alt and logo with the prefix neovim expects for it
+    const MODIFIER_PREFIXES: &[(bool, bool, bool, bool, &str)] = &[
+        (false, false, false, false, ""),
+        (true, false, false, false, "S-"),
+        (false, true, false, false, "C-"),
+        (true, true, false, false, "C-S-"),
+        (false, false, true, false, "M-"),
+        (true, false, true, false, "M-S-"),
+        (false, true, true, false, "M-C-"),
+        (true, true, true, false, "M-C-S-"),
+        (false, false, false, true, "D-"),
+        (true, false, false, true, "D-S-"),
+        (false, true, false, true, "D-C-"),
+        (true, true, false, true, "D-C-S-"),
+        (false, false, true, true, "D-M-"),
+        (true, false, true, true, "D-M-S-"),
+        (false, true, true, true, "D-M-C-"),
+        (true, true, true, true, "D-M-C-S-")
+    ];
+
+    #[test]
+    fn special_keys_with_modifiers() {
+        for &(keycode, name) in SPECIAL_KEYS {
+            for &(shift, ctrl, alt, logo, prefix) in MODIFIER_PREFIXES {
+                let modifiers = ModifiersState { shift, ctrl, alt, logo };
+                let (keycode_text, special) = parse_keycode(keycode).expect("Special key has no notation");
+                assert_eq!(
+                    append_modifiers(modifiers, keycode_text, special),
+                    format!("<{}{}>", prefix, name),
+                    "{:?} with {:?}", keycode, modifiers);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn keypad_navigation() {
+        const KEYPAD_KEYS: &[(u32, Option<VirtualKeyCode>, &str)] = &[
+            (71, Some(VirtualKeyCode::Home), "kHome"), (72, Some(VirtualKeyCode::Up), "kUp"),
+            (73, Some(VirtualKeyCode::PageUp), "kPageUp"), (75, Some(VirtualKeyCode::Left), "kLeft"),
+            (76, None, "kOrigin"), (77, Some(VirtualKeyCode::Right), "kRight"),
+            (79, Some(VirtualKeyCode::End), "kEnd"), (80, Some(VirtualKeyCode::Down), "kDown"),
+            (81, Some(VirtualKeyCode::PageDown), "kPageDown"), (82, Some(VirtualKeyCode::Insert), "kInsert"),
+            (83, Some(VirtualKeyCode::Delete), "kDel")
+        ];
+        for &(scancode, virtual_keycode, name) in KEYPAD_KEYS {
+            let mut handler = KeyboardHandler::new();
+            let input = KeyboardInput { scancode, state: ElementState::Pressed, virtual_keycode, modifiers: CTRL };
+            assert_eq!(handler.handle_keyboard_input(input), vec![format!("<C-{}>", name)]);
+        }
+
+        // The navigation block and the keypad with num lock on keep their own names
+        assert_eq!(parse_keypad_navigation(102, Some(VirtualKeyCode::Home)), None);
+        assert_eq!(parse_keypad_navigation(71, Some(VirtualKeyCode::Numpad7)), None);
+    }
+
+    #[test]
+    fn us_layout() {
+        assert_eq!(type_events(&[Key(VirtualKeyCode::A, NONE), Character('a')]), vec!["a"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key1, SHIFT), Character('!')]), vec!["!"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Comma, SHIFT), Character('<')]), vec!["<lt>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Backslash, SHIFT), Character('|')]), vec!["|"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Return, NONE), Character('\r')]), vec!["<Enter>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Escape, NONE), Character('\u{1b}')]), vec!["<ESC>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::X, CTRL), Character('\u{18}')]), vec!["<C-x>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::X, CTRL_SHIFT), Character('\u{18}')]), vec!["<C-S-x>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::X, ALT_SHIFT), Character('X')]), vec!["<M-S-x>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key6, CTRL_SHIFT), Character('\u{1e}')]), vec!["<C-^>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key1, ALT_SHIFT), Character('!')]), vec!["<M-!>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Comma, ALT_SHIFT), Character('<')]), vec!["<M-lt>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Backslash, CTRL), Character('\u{1c}')]), vec!["<C-Bslash>"]);
+    }
+
+    #[test]
+    fn german_layout() {
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key7, SHIFT), Character('/')]), vec!["/"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key8, ALTGR), Character('[')]), vec!["["]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key9, ALTGR), Character(']')]), vec!["]"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Q, ALTGR), Character('@')]), vec!["@"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Y, CTRL), Character('\u{1a}')]), vec!["<C-z>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Y, ALT_SHIFT), Character('Z')]), vec!["<M-S-z>"]);
+    }
+
+    #[test]
+    fn french_layout() {
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key1, NONE), Character('&')]), vec!["&"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key1, SHIFT), Character('1')]), vec!["1"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key5, ALTGR), Character('[')]), vec!["["]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Minus, ALTGR), Character(']')]), vec!["]"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Q, CTRL), Character('\u{1}')]), vec!["<C-a>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Q, CTRL_SHIFT), Character('\u{1}')]), vec!["<C-S-a>"]);
+    }
+
+    #[test]
+    fn dvorak_layout() {
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Minus, NONE), Character('[')]), vec!["["]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Minus, SHIFT), Character('{')]), vec!["{"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::W, SHIFT), Character('<')]), vec!["<lt>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::B, CTRL), Character('\u{18}')]), vec!["<C-x>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::B, CTRL_SHIFT), Character('\u{18}')]), vec!["<C-S-x>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::B, ALT_SHIFT), Character('X')]), vec!["<M-S-x>"]);
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn macos_option() {
+        // German layout, where Option+L types @
+        assert!(!is_chord(ALT, false));
+        assert_eq!(type_events_with_option(&[Key(VirtualKeyCode::L, ALT), Character('@')], false), vec!["@"]);
+        assert!(is_chord(ALT, true));
+        assert_eq!(type_events_with_option(&[Key(VirtualKeyCode::L, ALT), Character('@')], true), vec!["<M-@>"]);
+        assert!(is_chord(ModifiersState { ctrl: true, alt: true, ..NONE }, false));
+    }
+
+    #[test]
+    fn chord_without_character_falls_back_to_keycode() {
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Key1, ALT)]), vec!["<M-1>"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::X, CTRL_SHIFT)]), vec!["<C-S-x>"]);
+        assert_eq!(
+            type_events(&[Key(VirtualKeyCode::A, CTRL), Key(VirtualKeyCode::Escape, NONE)]),
+            vec!["<C-a>", "<ESC>"]);
+    }
+
+    #[test]
+    fn character_after_special_key_is_skipped_once() {
+        assert_eq!(
+            type_events(&[Key(VirtualKeyCode::Tab, NONE), Character('\t'), Key(VirtualKeyCode::A, NONE), Character('a')]),
+            vec!["<Tab>", "a"]);
+        assert_eq!(type_events(&[Key(VirtualKeyCode::Space, NONE), Character(' ')]), vec![" "]);
     }
 }
@@ -1,12 +1,20 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod clipboard;
 mod editor;
 mod events;
+mod file_drop;
 mod window;
+mod window_mode;
+mod window_state;
 mod keybindings;
+mod mouse;
 mod renderer;
+mod screenshot;
+mod settings;
 
 #[macro_use] extern crate derive_new;
+#[macro_use] extern crate lazy_static;
 
 use std::process::{Command, Stdio};
 use std::sync::{Arc, Mutex};
@@ -17,21 +25,60 @@ use neovim_lib::{Neovim, UiAttachOptions, Session};
 use window::ui_loop;
 use editor::Editor;
 use events::parse_neovim_event;
+use screenshot::take_screenshot;
+use settings::SETTINGS;
+use clipboard::{ClipboardHandler, create_clipboard, handle_selection_notification, setup_clipboard_provider};
+use window_state::{load_window_state, save_window_state};
 
 const INITIAL_WIDTH: u64 = 100;
 const INITIAL_HEIGHT: u64 = 50;
 
+// Arguments meant for neovide itself. Everything else is passed through to nvim.
+struct Arguments {
+    screenshot_path: Option<String>,
+    window_flags: Vec<&'static str>,
+    ignore_saved_state: bool,
+    nvim_arguments: Vec<String>
+}
+
+fn parse_arguments() -> Arguments {
+    let mut arguments = Arguments {
+        screenshot_path: None,
+        window_flags: Vec::new(),
+        ignore_saved_state: false,
+        nvim_arguments: Vec::new()
+    };
+
+    let mut remaining = std::env::args().skip(1);
+    while let Some(argument) = remaining.next() {
+        match argument.as_ref() {
+            "--screenshot" => {
+                arguments.screenshot_path = Some(remaining.next().expect("--screenshot requires an output path"));
+            },
+            // Each of these turns on the window mode setting of the same name
+            "--fullscreen" => arguments.window_flags.push("fullscreen"),
+            "--maximized" => arguments.window_flags.push("maximized"),
+            "--frameless" => arguments.window_flags.push("frameless"),
+            "--always-on-top" => arguments.window_flags.push("always_on_top"),
+            "--no-saved-state" => arguments.ignore_saved_state = true,
+            _ => arguments.nvim_arguments.push(argument)
+        }
+    }
+
+    arguments
+}
+
 #[cfg(target_os = "windows")]
 fn set_windows_creation_flags(cmd: &mut Command) {
     use std::os::windows::process::CommandExt;
     cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
 }
 
-fn create_nvim_command() -> Command {
+fn create_nvim_command(nvim_arguments: &[String]) -> Command {
     let mut cmd = Command::new("nvim");
 
     cmd.arg("--embed")
-        .args(std::env::args().skip(1))
+        .args(nvim_arguments)
         .stderr(Stdio::inherit());
 
     #[cfg(target_os = "windows")]
@@ -40,17 +87,31 @@ fn create_nvim_command() -> Command {
     cmd
 }
 
-fn start_nvim(editor: Arc<Mutex<Editor>>) -> Neovim {
-    let mut cmd = create_nvim_command();
+fn start_nvim(editor: Arc<Mutex<Editor>>, nvim_arguments: &[String]) -> Neovim {
+    let mut cmd = create_nvim_command(nvim_arguments);
     let mut session = Session::new_child_cmd(&mut cmd).unwrap();
-    let receiver = session.start_event_loop_channel();
+    let clipboard = create_clipboard();
+    let receiver = match &clipboard {
+        Some(clipboard) => session.start_event_loop_channel_handler(ClipboardHandler::new(clipboard.clone())),
+        None => session.start_event_loop_channel()
+    };
     let join_handle = session.take_dispatch_guard();
     let mut nvim = Neovim::new(session);
     let mut options = UiAttachOptions::new();
     options.set_cmdline_external(false);
     options.set_messages_external(false);
     options.set_linegrid_external(true);
+    options.set_hlstate_external(true);
     options.set_rgb(true);
+    SETTINGS.read_initial_values(&mut nvim);
+    SETTINGS.setup_change_listener(&mut nvim);
+    if clipboard.is_some() {
+        setup_clipboard_provider(&mut nvim);
+    }
+    // The working directory is mirrored the same way for the {cwd} placeholder of the title
+    if let Err(error) = nvim.command("let g:neovide_cwd = getcwd() | autocmd DirChanged * let g:neovide_cwd = getcwd()") {
+        println!("Could not track the working directory: {}", error);
+    }
     nvim.ui_attach(INITIAL_WIDTH as i64, INITIAL_HEIGHT as i64, &options).unwrap();
 
     // Listen to neovim events
@@ -58,6 +119,20 @@ fn start_nvim(editor: Arc<Mutex<Editor>>) -> Neovim {
         println!("UI thread spawned");
         loop {
             let (event_name, events) = receiver.recv().expect("Could not receive event.");
+            if event_name == "setting_changed" {
+                SETTINGS.handle_changed_notification(events);
+                let editor = editor.lock().unwrap();
+                editor.window.as_ref().map(|window| window.request_redraw());
+                continue;
+            }
+
+            if event_name == "selection_changed" {
+                if let Some(clipboard) = &clipboard {
+                    handle_selection_notification(clipboard, events);
+                }
+                continue;
+            }
+
             let parsed_events = parse_neovim_event(event_name, events).expect("Event parse failed...");
             for event in parsed_events {
                 let mut editor = editor.lock().unwrap();
@@ -69,6 +144,7 @@ fn start_nvim(editor: Arc<Mutex<Editor>>) -> Neovim {
     // Quit process when nvim exits
     thread::spawn(move || {
         join_handle.join().expect("Could not join neovim process...");
+        save_window_state();
         std::process::exit(0);
     });
 
@@ -77,7 +153,16 @@ fn start_nvim(editor: Arc<Mutex<Editor>>) -> Neovim {
 
 fn main() {
     // env_logger::from_env(LoggerEnv::default().default_filter_or("warn")).init();
+    let arguments = parse_arguments();
     let editor = Arc::new(Mutex::new(Editor::new(INITIAL_WIDTH, INITIAL_HEIGHT)));
-    let nvim = start_nvim(editor.clone());
-    ui_loop(editor, nvim, (INITIAL_WIDTH, INITIAL_HEIGHT));
+    let nvim = start_nvim(editor.clone(), &arguments.nvim_arguments);
+
+    if let Some(screenshot_path) = arguments.screenshot_path {
+        take_screenshot(editor, &screenshot_path, (INITIAL_WIDTH, INITIAL_HEIGHT));
+        drop(nvim);
+        std::process::exit(0);
+    }
+
+    let saved_state = if arguments.ignore_saved_state { None } else { load_window_state() };
+    ui_loop(editor, nvim, (INITIAL_WIDTH, INITIAL_HEIGHT), saved_state, arguments.window_flags);
 }
@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use neovim_lib::{Neovim, NeovimApi};
+use skulpin::winit::event::{ElementState, ModifiersState, MouseButton};
+
+use crate::settings::SETTINGS;
+
+const DEFAULT_MULTICLICK_INTERVAL: u64 = 500;
+const MAX_CLICK_COUNT: u64 = 4;
+
+fn button_name(button: MouseButton) -> Option<&'static str> {
+    match button {
+        MouseButton::Left => Some("left"),
+        MouseButton::Right => Some("right"),
+        MouseButton::Middle => Some("middle"),
+        // Back and forward are reported as 4 and 5 on windows and as 8 and 9 on x11
+        MouseButton::Other(4) | MouseButton::Other(8) => Some("x1"),
+        MouseButton::Other(5) | MouseButton::Other(9) => Some("x2"),
+        MouseButton::Other(_) => None
+    }
+}
+
+fn modifier_string(modifiers: ModifiersState, click_count: u64) -> String {
+    let mut result = String::new();
+    if modifiers.shift {
+        result.push_str("S-");
+    }
+    if modifiers.ctrl {
+        result.push_str("C-");
+    }
+    if modifiers.alt {
+        result.push_str("A-");
+    }
+    if modifiers.logo {
+        result.push_str("D-");
+    }
+    if click_count > 1 {
+        result.push_str(&format!("{}-", click_count));
+    }
+    result
+}
+
+// Tracks the mouse between winit events and translates them into nvim_input_mouse calls.
+// Positions are in grid cells.
+pub struct MouseHandler {
+    position: (i64, i64),
+    drag_button: Option<&'static str>,
+    last_click: Option<(&'static str, (i64, i64), Instant)>,
+    click_count: u64,
+    scroll_remainder: (f32, f32)
+}
+
+impl MouseHandler {
+    pub fn new() -> MouseHandler {
+        MouseHandler {
+            position: (0, 0),
+            drag_button: None,
+            last_click: None,
+            click_count: 0,
+            scroll_remainder: (0.0, 0.0)
+        }
+    }
+
+    pub fn handle_cursor_moved(&mut self, nvim: &mut Neovim, grid_position: (i64, i64), modifiers: ModifiersState) {
+        if grid_position == self.position {
+            return;
+        }
+
+        self.position = grid_position;
+        if let Some(button) = self.drag_button {
+            let (grid_x, grid_y) = grid_position;
+            nvim.input_mouse(button, "drag", &modifier_string(modifiers, 1), 0, grid_y, grid_x)
+                .expect("Could not send mouse input");
+        }
+    }
+
+    pub fn handle_mouse_input(&mut self, nvim: &mut Neovim, state: ElementState, button: MouseButton, modifiers: ModifiersState) {
+        let button = match button_name(button) {
+            Some(button) => button,
+            None => return
+        };
+
+        let (grid_x, grid_y) = self.position;
+        match state {
+            ElementState::Pressed => {
+                // Presses count as one click until the interval has passed or the mouse has left
+                // the cell of the previous press
+                let interval = Duration::from_millis(SETTINGS.get_u64("multiclick_interval", DEFAULT_MULTICLICK_INTERVAL));
+                let repeated = match self.last_click {
+                    Some((last_button, last_position, last_time)) =>
+                        last_button == button && last_position == self.position && last_time.elapsed() <= interval,
+                    None => false
+                };
+                self.click_count = if repeated { self.click_count % MAX_CLICK_COUNT + 1 } else { 1 };
+                self.last_click = Some((button, self.position, Instant::now()));
+                self.drag_button = Some(button);
+
+                nvim.input_mouse(button, "press", &modifier_string(modifiers, self.click_count), 0, grid_y, grid_x)
+                    .expect("Could not send mouse input");
+            },
+            ElementState::Released => {
+                if self.drag_button == Some(button) {
+                    self.drag_button = None;
+                }
+
+                nvim.input_mouse(button, "release", &modifier_string(modifiers, 1), 0, grid_y, grid_x)
+                    .expect("Could not send mouse input");
+            }
+        }
+    }
+
+    // Scroll amounts are in lines. Fractions from touchpads are kept until they add up to a
+    // whole line.
+    pub fn handle_scroll(&mut self, nvim: &mut Neovim, lines: (f32, f32), modifiers: ModifiersState) {
+        let (horizontal, vertical) = lines;
+        let (remaining_horizontal, remaining_vertical) = self.scroll_remainder;
+        let horizontal = remaining_horizontal + horizontal;
+        let vertical = remaining_vertical + vertical;
+
+        let (grid_x, grid_y) = self.position;
+        let modifiers = modifier_string(modifiers, 1);
+
+        let vertical_input_type = if vertical > 0.0 { "up" } else { "down" };
+        for _ in 0..(vertical.abs() as u64) {
+            nvim.input_mouse("wheel", vertical_input_type, &modifiers, 0, grid_y, grid_x)
+                .expect("Could not send mouse input");
+        }
+
+        let horizontal_input_type = if horizontal > 0.0 { "right" } else { "left" };
+        for _ in 0..(horizontal.abs() as u64) {
+            nvim.input_mouse("wheel", horizontal_input_type, &modifiers, 0, grid_y, grid_x)
+                .expect("Could not send mouse input");
+        }
+
+        self.scroll_remainder = (horizontal.fract(), vertical.fract());
+    }
+}
@@ -0,0 +1,35 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    Quadratic,
+    Cubic,
+    Exponential
+}
+
+impl Easing {
+    pub fn from_name(name: &str) -> Option<Easing> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "quadratic" => Some(Easing::Quadratic),
+            "cubic" => Some(Easing::Cubic),
+            "exponential" | "expo" => Some(Easing::Exponential),
+            _ => None
+        }
+    }
+
+    // Maps animation progress in 0..1 onto eased progress in 0..1. All of the curves ease out,
+    // so motion starts fast and settles gently into the destination.
+    pub fn ease(&self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::Quadratic => 1.0 - (1.0 - t).powi(2),
+            Easing::Cubic => 1.0 - (1.0 - t).powi(3),
+            Easing::Exponential => if t >= 1.0 { 1.0 } else { 1.0 - 2.0f32.powf(-10.0 * t) }
+        }
+    }
+}
+
+pub fn lerp(start: f32, end: f32, t: f32) -> f32 {
+    start + (end - start) * t
+}
@@ -1,34 +1,196 @@
+use std::collections::HashMap;
+
+use harfbuzz_rs::{Blob, Face, Font as HarfBuzzFont, Owned, UnicodeBuffer};
 use lru::LruCache;
-use skulpin::skia_safe::{Shaper, TextBlob, Font, Point};
+use skulpin::skia_safe::{Shaper, TextBlob, TextBlobBuilder, Font, Point};
+
+use super::font_features::FontFeature;
+use crate::settings::SETTINGS;
+
+// Rough memory cost of a cached blob. Blobs store a glyph id and a position per glyph plus
+// some fixed bookkeeping, and the key keeps its own copy of the text.
+const BLOB_OVERHEAD_BYTES: usize = 64;
+const BYTES_PER_GLYPH: usize = 2 + 2 * 4;
+const DEFAULT_CACHE_BUDGET_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ShapeKey {
+    text: String,
+    typeface_id: u32,
+    size_bits: u32,
+    skew_bits: u32,
+    embolden: bool,
+    features: Vec<FontFeature>
+}
+
+impl ShapeKey {
+    fn new(text: String, font: &Font, features: &[FontFeature]) -> ShapeKey {
+        ShapeKey {
+            text,
+            features: features.to_vec(),
+            typeface_id: font.typeface().map(|typeface| typeface.unique_id()).unwrap_or(0),
+            size_bits: font.size().to_bits(),
+            skew_bits: font.skew_x().to_bits(),
+            embolden: font.is_embolden()
+        }
+    }
+
+    fn estimated_bytes(&self) -> usize {
+        BLOB_OVERHEAD_BYTES + self.text.len() + self.text.chars().count() * BYTES_PER_GLYPH
+            + self.features.len() * std::mem::size_of::<FontFeature>()
+    }
+}
 
+#[derive(Debug, Clone, Default)]
+pub struct ShapingCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize
+}
+
+impl ShapingCacheStats {
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f32 / total as f32
+        }
+    }
+}
+
+// Skia's shaper uses harfbuzz internally but doesn't accept a feature list, so runs with
+// features are shaped by harfbuzz directly and turned into a blob here. Harfbuzz fonts are
+// created from the data of the skia typeface and kept per typeface.
 pub struct CachingShaper {
     shaper: Shaper,
-    cache: LruCache<(String, u16), TextBlob>
+    harfbuzz_fonts: HashMap<u32, Owned<HarfBuzzFont<'static>>>,
+    // Cell width per unit of font size
+    cell_width: f32,
+    cache: LruCache<ShapeKey, TextBlob>,
+    budget_bytes: usize,
+    stats: ShapingCacheStats
 }
 
 impl CachingShaper {
     pub fn new() -> CachingShaper {
         CachingShaper {
             shaper: Shaper::new(None),
-            cache: LruCache::new(1000)
+            harfbuzz_fonts: HashMap::new(),
+            cell_width: 0.0,
+            cache: LruCache::unbounded(),
+            budget_bytes: SETTINGS.get_u64("shaping_cache_budget", DEFAULT_CACHE_BUDGET_BYTES as u64) as usize,
+            stats: ShapingCacheStats::default()
         }
     }
 
-    pub fn shape(&self, text: &str, font: &Font) -> TextBlob {
+    pub fn shape(&mut self, text: &str, font: &Font, features: &[FontFeature]) -> TextBlob {
+        if !features.is_empty() {
+            if let Some(blob) = self.shape_with_features(text, font, features) {
+                return blob;
+            }
+        }
+
         let (blob, _) = self.shaper.shape_text_blob(text, font, true, 1000000.0, Point::default()).unwrap();
         blob
     }
 
-    pub fn shape_cached(&mut self, text: String, scale: u16, font: &Font) -> &TextBlob {
-        let key = (text.clone(), scale);
-        if !self.cache.contains(&key) {
-            self.cache.put(key.clone(), self.shape(&text, &font));
+    // Returns None when the font can't be loaded into harfbuzz or is missing a glyph, in which
+    // case the text is shaped without features so that skia's font fallback applies.
+    fn shape_with_features(&mut self, text: &str, font: &Font, features: &[FontFeature]) -> Option<TextBlob> {
+        let typeface = font.typeface()?;
+        let typeface_id = typeface.unique_id();
+        if !self.harfbuzz_fonts.contains_key(&typeface_id) {
+            let (data, index) = typeface.to_font_data()?;
+            let face = Face::new(Blob::with_bytes_owned(data, |data| data), index as u32);
+            self.harfbuzz_fonts.insert(typeface_id, HarfBuzzFont::new(face));
+        }
+        let harfbuzz_font = &self.harfbuzz_fonts[&typeface_id];
+
+        let features = features.iter().map(FontFeature::to_harfbuzz).collect::<Vec<_>>();
+        let buffer = UnicodeBuffer::new().add_str(text);
+        let output = harfbuzz_rs::shape(harfbuzz_font, buffer, &features);
+        let infos = output.get_glyph_infos();
+        let positions = output.get_glyph_positions();
+        if infos.iter().any(|info| info.codepoint == 0) {
+            return None;
+        }
+
+        // Harfbuzz positions are in font units. Like the skia shaper, the blob is placed with
+        // the top of the line at the origin. Advances are rounded to whole cells, otherwise the
+        // fractional advances add up and long runs drift off the grid. Ligatures and wide glyphs
+        // span several cells and combining marks none.
+        let cell_width = self.cell_width * font.size();
+        let scale = font.size() / typeface.units_per_em()? as f32;
+        let (_, metrics) = font.metrics();
+        let baseline = -metrics.ascent;
+
+        let mut builder = TextBlobBuilder::new();
+        let (glyphs, points) = builder.alloc_run_pos(font, infos.len(), None);
+        let mut advance = 0.0;
+        for (index, (info, position)) in infos.iter().zip(positions).enumerate() {
+            glyphs[index] = info.codepoint as u16;
+            points[index] = Point::new(
+                advance + position.x_offset as f32 * scale,
+                baseline - position.y_offset as f32 * scale);
+            let glyph_advance = position.x_advance as f32 * scale;
+            advance += if cell_width > 0.0 {
+                (glyph_advance / cell_width).round() * cell_width
+            } else {
+                glyph_advance
+            };
+        }
+        builder.make()
+    }
+
+    // Cached blobs are keyed on the typeface and the synthetic style applied to it as well as
+    // the size, features and text, so the same text shaped with different fonts never shares a
+    // blob.
+    pub fn shape_cached(&mut self, text: String, font: &Font, features: &[FontFeature]) -> &TextBlob {
+        let key = ShapeKey::new(text, font, features);
+        if self.cache.contains(&key) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            let blob = self.shape(&key.text, &font, features);
+            self.stats.bytes += key.estimated_bytes();
+            self.cache.put(key.clone(), blob);
+            self.evict_to_budget();
         }
 
         self.cache.get(&key).unwrap()
     }
 
+    fn evict_to_budget(&mut self) {
+        // Always keep the most recent entry even if it alone is over budget
+        while self.stats.bytes > self.budget_bytes && self.cache.len() > 1 {
+            match self.cache.pop_lru() {
+                Some((evicted_key, _)) => self.stats.bytes -= evicted_key.estimated_bytes(),
+                None => break
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ShapingCacheStats {
+        ShapingCacheStats {
+            entries: self.cache.len(),
+            .. self.stats.clone()
+        }
+    }
+
+    // Glyphs shaped with font features are placed on cells of this width
+    pub fn set_cell_width(&mut self, cell_width: f32, font_size: f32) {
+        let cell_width = cell_width / font_size;
+        if (cell_width - self.cell_width).abs() > std::f32::EPSILON {
+            self.cell_width = cell_width;
+            self.clear();
+        }
+    }
+
     pub fn clear(&mut self) {
         self.cache.clear();
+        self.harfbuzz_fonts.clear();
+        self.stats.bytes = 0;
     }
 }
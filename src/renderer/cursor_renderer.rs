@@ -1,71 +1,135 @@
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use skulpin::skia_safe::{Canvas, Paint, Path, Point};
+use skulpin::skia_safe::{Canvas, Paint, PaintStyle, Path, Point, Rect};
 
-use crate::renderer::{CachingShaper, FontLookup};
-use crate::editor::{Colors, Cursor, CursorShape, Editor};
+use crate::renderer::{CachingShaper, FontLookup, draw_emoji};
+use crate::renderer::animation_utils::Easing;
+use crate::renderer::font_features::FontFeatureSettings;
+use crate::renderer::cursor_vfx::{CursorVfx, VfxSettings};
+use crate::editor::{Colors, Cursor, CursorShape, Editor, Style, is_emoji_cluster};
+use crate::settings::SETTINGS;
 
-const AVERAGE_MOTION_PERCENTAGE: f32 = 0.6;
-const MOTION_PERCENTAGE_SPREAD: f32 = 0.5;
+const DEFAULT_ANIMATION_LENGTH: f32 = 0.13;
+const DEFAULT_SHORT_ANIMATION_LENGTH: f32 = 0.04;
+// Corners leading the motion finish this much sooner and trailing corners this much later than
+// the configured length, which smears the cursor toward its destination.
+const CORNER_LENGTH_SPREAD: f32 = 0.5;
 
 const DEFAULT_CELL_PERCENTAGE: f32 = 1.0 / 8.0;
 
 const STANDARD_CORNERS: &[(f32, f32); 4] = &[(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
 
+// Jumps within a line use the short settings so that typing and moving by words stays snappy,
+// while jumps to another line use the long ones.
+#[derive(Debug, Clone, Copy)]
+pub struct CursorAnimationSettings {
+    pub length: f32,
+    pub easing: Easing
+}
+
+impl CursorAnimationSettings {
+    pub fn immediate() -> CursorAnimationSettings {
+        CursorAnimationSettings { length: 0.0, easing: Easing::Linear }
+    }
+
+    pub fn from_settings(short_jump: bool) -> CursorAnimationSettings {
+        if !SETTINGS.get_bool("cursor_animate", true) {
+            return CursorAnimationSettings::immediate();
+        }
+
+        let (length_name, easing_name, default_length) = if short_jump {
+            ("cursor_short_animation_length", "cursor_short_animation_easing", DEFAULT_SHORT_ANIMATION_LENGTH)
+        } else {
+            ("cursor_animation_length", "cursor_animation_easing", DEFAULT_ANIMATION_LENGTH)
+        };
+
+        CursorAnimationSettings {
+            length: SETTINGS.get_f32(length_name, default_length).max(0.0),
+            easing: SETTINGS.get_string(easing_name)
+                .and_then(|name| Easing::from_name(&name))
+                .unwrap_or(Easing::Cubic)
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Corner {
+    pub start_position: Point,
     pub current_position: Point,
     pub relative_position: Point,
+    pub previous_destination: Point,
+    pub t: f32
 }
 
 impl Corner {
     pub fn new(relative_position: Point) -> Corner {
         Corner {
+            start_position: Point::new(0.0, 0.0),
             current_position: Point::new(0.0, 0.0),
-            relative_position
+            relative_position,
+            previous_destination: Point::new(0.0, 0.0),
+            t: 1.0
         }
     }
 
-    pub fn update(&mut self, font_dimensions: Point, destination: Point) -> bool {
-        let relative_scaled_position: Point = 
-            (self.relative_position.x * font_dimensions.x, self.relative_position.y * font_dimensions.y).into();
+    pub fn scaled_position(&self, font_dimensions: Point) -> Point {
+        (self.relative_position.x * font_dimensions.x, self.relative_position.y * font_dimensions.y).into()
+    }
+
+    // Advances the corner by dt seconds toward the destination. Progress is measured in time
+    // rather than in frames so the animation looks the same at any refresh rate.
+    pub fn update(&mut self, font_dimensions: Point, destination: Point, dt: f32, settings: &CursorAnimationSettings) -> bool {
+        let relative_scaled_position = self.scaled_position(font_dimensions);
         let corner_destination = destination + relative_scaled_position;
 
-        let delta = corner_destination - self.current_position;
-
-        if delta.length() > 0.0 {
-            // Project relative_scaled_position (actual possition of the corner relative to the
-            // center of the cursor) onto the remaining distance vector. This gives us the relative
-            // distance to the destination along the delta vector which we can then use to scale the
-            // motion_percentage.
-            let motion_scale = delta.dot(relative_scaled_position) / delta.length() / font_dimensions.length();
-
-            // The motion_percentage is then equal to the motion_scale factor times the
-            // MOTION_PERCENTAGE_SPREAD and added to the AVERAGE_MOTION_PERCENTAGE. This way all of
-            // the percentages are positive and spread out by the spread constant.
-            let motion_percentage = motion_scale * MOTION_PERCENTAGE_SPREAD + AVERAGE_MOTION_PERCENTAGE;
-
-            // Then the current_position is animated by taking the delta vector, multiplying it by
-            // the motion_percentage and adding the resulting value to the current position causing
-            // the cursor to "jump" toward the target destination. Since further away corners jump
-            // slower, the cursor appears to smear toward the destination in a satisfying and
-            // visually trackable way.
-            let delta = corner_destination - self.current_position;
-            self.current_position += delta * motion_percentage;
+        if corner_destination != self.previous_destination {
+            // A new jump starts from wherever the corner currently is. The frame the jump starts
+            // on does not advance it, since dt may include time spent idle before the jump.
+            self.start_position = self.current_position;
+            self.previous_destination = corner_destination;
+            self.t = if settings.length > 0.0 { 0.0 } else { 1.0 };
+        } else if self.t < 1.0 {
+            let delta = corner_destination - self.start_position;
+
+            // Project relative_scaled_position (actual position of the corner relative to the
+            // center of the cursor) onto the jump. Corners ahead of the center along the jump get
+            // a shorter animation and corners behind it a longer one.
+            let motion_scale = if delta.length() > 0.0 {
+                delta.dot(relative_scaled_position) / delta.length() / font_dimensions.length()
+            } else {
+                0.0
+            };
+            let length = settings.length * (1.0 - motion_scale * CORNER_LENGTH_SPREAD);
+
+            self.t = if length > 0.0 { (self.t + dt / length).min(1.0) } else { 1.0 };
         }
 
-        delta.length() > 0.001
+        let delta = corner_destination - self.start_position;
+        self.current_position = self.start_position + delta * settings.easing.ease(self.t);
+
+        self.t < 1.0
     }
 }
 
 pub struct CursorRenderer {
-    pub corners: Vec<Corner>
+    pub corners: Vec<Corner>,
+    previous_row: Option<u64>,
+    animation_settings: CursorAnimationSettings,
+    last_frame: Instant,
+    previous_mode: Option<(CursorShape, Option<f32>)>,
+    vfx: CursorVfx
 }
 
 impl CursorRenderer {
     pub fn new() -> CursorRenderer {
         let mut renderer = CursorRenderer {
-            corners: vec![Corner::new((0.0, 0.0).into()); 4]
+            corners: vec![Corner::new((0.0, 0.0).into()); 4],
+            previous_row: None,
+            animation_settings: CursorAnimationSettings::from_settings(false),
+            last_frame: Instant::now(),
+            previous_mode: None,
+            vfx: CursorVfx::new()
         };
         renderer.set_cursor_shape(&CursorShape::Block, DEFAULT_CELL_PERCENTAGE);
         renderer
@@ -99,29 +163,75 @@ impl CursorRenderer {
             font_width: f32, font_height: f32,
             paint: &mut Paint, editor: Arc<Mutex<Editor>>,
             shaper: &mut CachingShaper, fonts_lookup: &mut FontLookup,
-            canvas: &mut Canvas) -> bool {
-        let (grid_x, grid_y) = cursor.position;
-        let font_dimensions: Point = (font_width, font_height).into();
-        let center_destination: Point = (
-            grid_x as f32 * font_width + font_width / 2.0, 
-            grid_y as f32 * font_height + font_height / 2.0
-        ).into();
+            font_features: &FontFeatureSettings, canvas: &mut Canvas) -> bool {
+        // The editor stores the cursor position as (row, column)
+        let (cursor_grid_y, cursor_grid_x) = cursor.position;
+        let (character, cell_style, double_width, focused) = {
+            let editor = editor.lock().unwrap();
+            let row = editor.grid.get(cursor_grid_y as usize);
+            let cell = row
+                .and_then(|row| row.get(cursor_grid_x as usize))
+                .cloned()
+                .flatten();
+            // The cursor covers every cell of wide characters and emoji
+            let double_width = editor.cell_count(cursor.position) > 1;
+            let (character, style) = cell.unwrap_or((" ".to_string(), None));
+            (character, style.unwrap_or(Style::new(default_colors.clone())), double_width, editor.focused)
+        };
 
-        self.set_cursor_shape(&cursor.shape, cursor.cell_percentage.unwrap_or(DEFAULT_CELL_PERCENTAGE));
+        let cell_count = if double_width { 2.0 } else { 1.0 };
+        let font_dimensions: Point = (font_width * cell_count, font_height).into();
+        let cell_top_left: Point = (cursor_grid_x as f32 * font_width, cursor_grid_y as f32 * font_height).into();
+        let center_destination = cell_top_left + font_dimensions * 0.5;
+
+        // Unfocused windows always show an outline of the whole cell
+        if focused {
+            self.set_cursor_shape(&cursor.shape, cursor.cell_percentage.unwrap_or(DEFAULT_CELL_PERCENTAGE));
+        } else {
+            self.set_cursor_shape(&CursorShape::Block, DEFAULT_CELL_PERCENTAGE);
+        }
+
+        let now = Instant::now();
+        let dt = (now - self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        // The length and easing are picked when a jump starts and kept until it finishes
+        if self.previous_row != Some(cursor_grid_y) || self.corners.iter().all(|corner| corner.t >= 1.0) {
+            let short_jump = self.previous_row == Some(cursor_grid_y);
+            self.animation_settings = CursorAnimationSettings::from_settings(short_jump);
+            self.previous_row = Some(cursor_grid_y);
+        }
+
+        // Nothing animates while the window is in the background
+        let animation_settings = if focused {
+            self.animation_settings
+        } else {
+            CursorAnimationSettings::immediate()
+        };
 
         let mut animating = false;
         if !center_destination.is_zero() {
             for corner in self.corners.iter_mut() {
-                let corner_animating = corner.update(font_dimensions, center_destination);
+                let corner_animating = corner.update(font_dimensions, center_destination, dt, &animation_settings);
                 animating = animating || corner_animating;
             }
         }
 
+        let vfx_settings = VfxSettings::from_settings();
+        let mode = (cursor.shape.clone(), cursor.cell_percentage);
+        if self.previous_mode.as_ref().map(|previous_mode| previous_mode != &mode).unwrap_or(false) {
+            self.vfx.mode_changed(&vfx_settings);
+        }
+        self.previous_mode = Some(mode);
 
-        if cursor.enabled {
-            // Draw Background
-            paint.set_color(cursor.background(&default_colors).to_color());
+        let mut current_center: Point = (0.0, 0.0).into();
+        for corner in self.corners.iter() {
+            current_center += corner.current_position * 0.25;
+        }
+        let vfx_animating = focused && self.vfx.update(&vfx_settings, current_center, font_dimensions);
+        animating = animating || vfx_animating;
 
+        if cursor.enabled {
             // The cursor is made up of four points, so I create a path with each of the four
             // corners.
             let mut path = Path::new();
@@ -130,25 +240,46 @@ impl CursorRenderer {
             path.line_to(self.corners[2].current_position);
             path.line_to(self.corners[3].current_position);
             path.close();
-            canvas.draw_path(&path, &paint);
 
-            let mut position_sum: Point = (0.0, 0.0).into();
-            for i in 0..4 {
-                position_sum += self.corners[i].current_position;
-            }
-            let Point { x: cursor_x, y: cursor_y } = position_sum * (1.0 / 4.0) - font_dimensions * 0.5;
-
-            // Draw foreground
-            if let CursorShape::Block = cursor.shape {
-                let (cursor_grid_y, cursor_grid_x) = cursor.position;
-                paint.set_color(cursor.foreground(&default_colors).to_color());
-                let editor = editor.lock().unwrap();
-                let character = editor.grid[cursor_grid_x as usize][cursor_grid_y as usize].clone()
-                    .map(|(character, _)| character)
-                    .unwrap_or(' ');
-                canvas.draw_text_blob(
-                    shaper.shape_cached(character.to_string(), 1, &fonts_lookup.size(1).normal), 
-                    (cursor_x, cursor_y), &paint);
+            paint.set_color(cursor.background(&cell_style, &default_colors).to_color());
+
+            if focused {
+                canvas.draw_path(&path, &paint);
+
+                // While animating, the character moves along with the cursor. It is offset by
+                // however far the corners currently are from their destinations.
+                let mut destination_center: Point = (0.0, 0.0).into();
+                for corner in self.corners.iter() {
+                    destination_center += (center_destination + corner.scaled_position(font_dimensions)) * 0.25;
+                }
+                let text_position = cell_top_left + (current_center - destination_center);
+
+                // Clipping to the cursor makes vertical and horizontal cursors only recolor the
+                // part of the character they cover.
+                canvas.save();
+                canvas.clip_path(&path, None, Some(false));
+                paint.set_color(cursor.foreground(&cell_style, &default_colors).to_color());
+                if is_emoji_cluster(&character) {
+                    let emoji_rect = Rect::from_xywh(text_position.x, text_position.y, font_dimensions.x, font_dimensions.y);
+                    draw_emoji(canvas, shaper, fonts_lookup, paint, &character, emoji_rect);
+                } else {
+                    let features = font_features.features_for(&fonts_lookup.name, &cell_style, true);
+                    let font = fonts_lookup.size(1).get(&cell_style);
+                    let blob = shaper.shape_cached(character, font, &features);
+                    fonts_lookup.raster_options.draw_text_blob(canvas, blob, text_position, &paint);
+                }
+                canvas.restore();
+
+                let cursor_rect = Rect::from_xywh(cell_top_left.x, cell_top_left.y, font_dimensions.x, font_dimensions.y);
+                self.vfx.render(
+                    &vfx_settings, canvas,
+                    cursor.background(&cell_style, &default_colors),
+                    cursor_rect, font_dimensions);
+            } else {
+                paint.set_style(PaintStyle::Stroke);
+                paint.set_stroke_width(1.0);
+                canvas.draw_path(&path, &paint);
+                paint.set_style(PaintStyle::Fill);
             }
         }
 
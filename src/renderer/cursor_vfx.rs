@@ -0,0 +1,192 @@
+use std::time::Instant;
+
+use rand::Rng;
+use skulpin::skia_safe::{Canvas, Color4f, Paint, PaintStyle, Point, Rect};
+
+use crate::settings::SETTINGS;
+
+const DEFAULT_PARTICLE_DENSITY: f32 = 7.0;
+const DEFAULT_PARTICLE_LIFETIME: f32 = 1.2;
+const DEFAULT_PARTICLE_SPEED: f32 = 10.0;
+const DEFAULT_OPACITY: f32 = 200.0;
+const GLOW_PULSE_LENGTH: f32 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VfxMode {
+    Particles,
+    Railgun,
+    Torpedo,
+    Glow
+}
+
+impl VfxMode {
+    fn from_name(name: &str) -> Option<VfxMode> {
+        match name {
+            "particles" | "pixiedust" => Some(VfxMode::Particles),
+            "railgun" => Some(VfxMode::Railgun),
+            "torpedo" => Some(VfxMode::Torpedo),
+            "glow" => Some(VfxMode::Glow),
+            _ => None
+        }
+    }
+}
+
+// Read from g:neovide_cursor_vfx_mode, g:neovide_cursor_vfx_particle_density (particles per cell
+// travelled), g:neovide_cursor_vfx_particle_lifetime (seconds), g:neovide_cursor_vfx_opacity
+// (0 to 255) and g:neovide_cursor_vfx_color ("#rrggbb", defaulting to the cursor color).
+#[derive(Debug, Clone, PartialEq)]
+pub struct VfxSettings {
+    pub mode: Option<VfxMode>,
+    pub density: f32,
+    pub lifetime: f32,
+    pub opacity: f32,
+    pub color: Option<Color4f>
+}
+
+fn parse_hex_color(text: &str) -> Option<Color4f> {
+    let hex = text.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let packed = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color4f {
+        r: ((packed >> 16) & 0xff) as f32 / 255.0,
+        g: ((packed >> 8) & 0xff) as f32 / 255.0,
+        b: (packed & 0xff) as f32 / 255.0,
+        a: 1.0
+    })
+}
+
+impl VfxSettings {
+    pub fn from_settings() -> VfxSettings {
+        VfxSettings {
+            mode: SETTINGS.get_string("cursor_vfx_mode").and_then(|name| VfxMode::from_name(&name)),
+            density: SETTINGS.get_f32("cursor_vfx_particle_density", DEFAULT_PARTICLE_DENSITY).max(0.0),
+            lifetime: SETTINGS.get_f32("cursor_vfx_particle_lifetime", DEFAULT_PARTICLE_LIFETIME).max(0.01),
+            opacity: SETTINGS.get_f32("cursor_vfx_opacity", DEFAULT_OPACITY).max(0.0).min(255.0),
+            color: SETTINGS.get_string("cursor_vfx_color").and_then(|text| parse_hex_color(&text))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Particle {
+    position: Point,
+    velocity: Point,
+    lifetime: f32
+}
+
+pub struct CursorVfx {
+    particles: Vec<Particle>,
+    previous_position: Option<Point>,
+    pulse_start: Option<Instant>,
+    last_update: Instant
+}
+
+impl CursorVfx {
+    pub fn new() -> CursorVfx {
+        CursorVfx {
+            particles: Vec::new(),
+            previous_position: None,
+            pulse_start: None,
+            last_update: Instant::now()
+        }
+    }
+
+    pub fn mode_changed(&mut self, settings: &VfxSettings) {
+        if settings.mode == Some(VfxMode::Glow) {
+            self.pulse_start = Some(Instant::now());
+        }
+    }
+
+    // Emits particles for the distance the cursor moved since the last frame and advances the
+    // existing ones. Returns true while anything is still visible so the event loop only keeps
+    // waking up for live effects.
+    pub fn update(&mut self, settings: &VfxSettings, cursor_position: Point, font_dimensions: Point) -> bool {
+        let now = Instant::now();
+        let dt = (now - self.last_update).as_secs_f32().min(0.1);
+        self.last_update = now;
+
+        let previous_position = self.previous_position.replace(cursor_position).unwrap_or(cursor_position);
+        let travel = cursor_position - previous_position;
+        if let Some(mode) = settings.mode {
+            if travel.length() > 0.0 && mode != VfxMode::Glow {
+                self.emit(mode, settings, previous_position, travel, font_dimensions);
+            }
+        } else {
+            self.particles.clear();
+            self.pulse_start = None;
+        }
+
+        for particle in self.particles.iter_mut() {
+            particle.position += particle.velocity * dt;
+            particle.lifetime -= dt;
+        }
+        self.particles.retain(|particle| particle.lifetime > 0.0);
+
+        if let Some(pulse_start) = self.pulse_start {
+            if pulse_start.elapsed().as_secs_f32() > GLOW_PULSE_LENGTH {
+                self.pulse_start = None;
+            }
+        }
+
+        !self.particles.is_empty() || self.pulse_start.is_some()
+    }
+
+    fn emit(&mut self, mode: VfxMode, settings: &VfxSettings, start: Point, travel: Point, font_dimensions: Point) {
+        let mut rng = rand::thread_rng();
+        let cells_travelled = travel.length() / font_dimensions.x.max(1.0);
+        let count = (cells_travelled * settings.density).ceil() as usize;
+        let direction = travel * (1.0 / travel.length());
+        let normal = Point::new(-direction.y, direction.x);
+
+        for _ in 0..count {
+            let t: f32 = rng.gen();
+            let position = start + travel * t;
+            let (velocity, lifetime) = match mode {
+                // Sparks scatter in random directions from anywhere along the path
+                VfxMode::Particles => {
+                    let angle = rng.gen::<f32>() * std::f32::consts::PI * 2.0;
+                    let speed = DEFAULT_PARTICLE_SPEED * font_dimensions.y * rng.gen::<f32>() * 0.2;
+                    (Point::new(angle.cos(), angle.sin()) * speed, settings.lifetime * rng.gen_range(0.5, 1.0))
+                },
+                // A streak whose particles are pushed sideways off the line of travel
+                VfxMode::Railgun => {
+                    let side = if rng.gen::<bool>() { 1.0 } else { -1.0 };
+                    (normal * (side * DEFAULT_PARTICLE_SPEED * rng.gen::<f32>()), settings.lifetime * t)
+                },
+                // Particles stay where they were left so the trail fades from the tail
+                VfxMode::Torpedo => (Point::new(0.0, 0.0), settings.lifetime * t),
+                VfxMode::Glow => continue
+            };
+            self.particles.push(Particle { position, velocity, lifetime });
+        }
+    }
+
+    pub fn render(&self, settings: &VfxSettings, canvas: &mut Canvas, cursor_color: Color4f, cursor_rect: Rect, font_dimensions: Point) {
+        let color = settings.color.clone().unwrap_or(cursor_color);
+        let mut paint = Paint::new(color, None);
+        paint.set_anti_alias(true);
+
+        let base_size = font_dimensions.x.min(font_dimensions.y) * 0.3;
+        for particle in self.particles.iter() {
+            let life = (particle.lifetime / settings.lifetime).max(0.0).min(1.0);
+            paint.set_alpha((settings.opacity * life) as u8);
+            let size = match settings.mode {
+                Some(VfxMode::Torpedo) => base_size * (0.5 + life),
+                _ => base_size * life
+            };
+            canvas.draw_circle(particle.position, size / 2.0, &paint);
+        }
+
+        if let Some(pulse_start) = self.pulse_start {
+            let progress = (pulse_start.elapsed().as_secs_f32() / GLOW_PULSE_LENGTH).min(1.0);
+            let spread = font_dimensions.y * progress;
+            paint.set_style(PaintStyle::Stroke);
+            paint.set_stroke_width(font_dimensions.y * 0.15 * (1.0 - progress) + 1.0);
+            paint.set_alpha((settings.opacity * (1.0 - progress)) as u8);
+            canvas.draw_rect(cursor_rect.with_outset((spread, spread)), &paint);
+        }
+    }
+
+}
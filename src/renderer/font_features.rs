@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use harfbuzz_rs::{Feature, Tag};
+use rmpv::Value;
+
+use crate::editor::Style;
+use crate::settings::SETTINGS;
+
+// The settings FontFeatureSettings::update_from_settings reads
+pub const FONT_FEATURE_SETTINGS: &[&str] = &["font_features", "highlight_font_features", "cursor_font_features"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FontFeature {
+    pub tag: String,
+    pub value: u32
+}
+
+impl FontFeature {
+    // Parses the same syntax used by most terminals and editors: "+ss01" or "ss01" enable a
+    // feature, "-calt" disables it and "cv05=2" selects an alternate.
+    pub fn parse(text: &str) -> Option<FontFeature> {
+        let text = text.trim();
+        let (tag, value) = if text.starts_with('+') {
+            (&text[1..], 1)
+        } else if text.starts_with('-') {
+            (&text[1..], 0)
+        } else if let Some(separator) = text.find('=') {
+            (&text[..separator], text[separator + 1..].trim().parse().ok()?)
+        } else {
+            (text, 1)
+        };
+
+        if tag.len() == 4 && tag.chars().all(|character| character.is_ascii_alphanumeric()) {
+            Some(FontFeature { tag: tag.to_string(), value })
+        } else {
+            None
+        }
+    }
+
+    // Applies to the whole shaped run
+    pub fn to_harfbuzz(&self) -> Feature {
+        let mut tag = self.tag.chars();
+        let mut next = || tag.next().unwrap_or(' ');
+        Feature::new(Tag::new(next(), next(), next(), next()), self.value, ..)
+    }
+}
+
+fn merge_features(features: &mut Vec<FontFeature>, overrides: &[FontFeature]) {
+    for feature in overrides {
+        features.retain(|existing| existing.tag != feature.tag);
+        features.push(feature.clone());
+    }
+}
+
+fn parse_feature_list(value: &Value) -> Vec<FontFeature> {
+    let mut features = Vec::new();
+    if let Value::Array(items) = value {
+        for item in items {
+            match item.as_str().and_then(FontFeature::parse) {
+                Some(feature) => features.push(feature),
+                None => println!("Invalid font feature: {}", item)
+            }
+        }
+    }
+    features
+}
+
+fn parse_feature_map(value: &Value) -> HashMap<String, Vec<FontFeature>> {
+    let mut features = HashMap::new();
+    if let Value::Map(entries) = value {
+        for (name, list) in entries {
+            if let Some(name) = name.as_str() {
+                features.insert(name.to_string(), parse_feature_list(list));
+            }
+        }
+    }
+    features
+}
+
+// Features come from g:neovide_font_features, which is either a list applied to every font or a
+// dictionary from font name to list, and from g:neovide_highlight_font_features, a dictionary
+// from highlight group name to list. Highlight features override font features with the same
+// tag, and g:neovide_cursor_font_features, a list, overrides both for the text under the
+// cursor. Setting it to ['-calt', '-liga'] for example breaks ligatures up only while the
+// cursor is on them.
+#[derive(Debug, Clone, Default)]
+pub struct FontFeatureSettings {
+    source: Option<(Option<Value>, Option<Value>, Option<Value>)>,
+    all_fonts: Vec<FontFeature>,
+    per_font: HashMap<String, Vec<FontFeature>>,
+    per_highlight: HashMap<String, Vec<FontFeature>>,
+    under_cursor: Vec<FontFeature>
+}
+
+impl FontFeatureSettings {
+    pub fn new() -> FontFeatureSettings {
+        FontFeatureSettings::default()
+    }
+
+    // Returns true if the features changed since the last update
+    pub fn update_from_settings(&mut self) -> bool {
+        let source = (
+            SETTINGS.get("font_features"),
+            SETTINGS.get("highlight_font_features"),
+            SETTINGS.get("cursor_font_features"));
+        if self.source.as_ref() == Some(&source) {
+            return false;
+        }
+
+        let (font_features, highlight_features, cursor_features) = &source;
+        self.all_fonts = Vec::new();
+        self.per_font = HashMap::new();
+        match font_features {
+            Some(value @ Value::Array(_)) => self.all_fonts = parse_feature_list(value),
+            Some(value @ Value::Map(_)) => self.per_font = parse_feature_map(value),
+            _ => {}
+        }
+        self.per_highlight = highlight_features.as_ref().map(parse_feature_map).unwrap_or_default();
+        self.under_cursor = cursor_features.as_ref().map(parse_feature_list).unwrap_or_default();
+
+        self.source = Some(source);
+        true
+    }
+
+    pub fn features_for(&self, font_name: &str, style: &Style, under_cursor: bool) -> Vec<FontFeature> {
+        let mut features = self.per_font.get(font_name)
+            .cloned()
+            .unwrap_or_else(|| self.all_fonts.clone());
+
+        for highlight_name in style.highlight_names.iter() {
+            if let Some(highlight_features) = self.per_highlight.get(highlight_name) {
+                merge_features(&mut features, highlight_features);
+            }
+        }
+
+        if under_cursor {
+            merge_features(&mut features, &self.under_cursor);
+        }
+
+        features
+    }
+}
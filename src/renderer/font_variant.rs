@@ -0,0 +1,131 @@
+use skulpin::skia_safe::{Font, FontStyle, Typeface};
+use skulpin::skia_safe::font_style::{Slant, Weight};
+
+use crate::settings::SETTINGS;
+
+const SYNTHETIC_ITALIC_SKEW: f32 = -0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntheticStyles {
+    // Synthesize a style only when the font doesn't ship a face for it
+    Auto,
+    // Always derive bold and italic from the regular face
+    Always,
+    // Never synthesize, falling back to the closest real face
+    Never
+}
+
+// The settings StyleOptions::from_settings reads
+pub const STYLE_SETTINGS: &[&str] = &["synthetic_styles", "disable_bold", "disable_italic", "altfont"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleOptions {
+    pub synthetic: SyntheticStyles,
+    pub bold_enabled: bool,
+    pub italic_enabled: bool,
+    pub altfont: Option<String>
+}
+
+impl StyleOptions {
+    // Reads g:neovide_synthetic_styles ("auto", "always" or "never"), g:neovide_disable_bold,
+    // g:neovide_disable_italic and g:neovide_altfont, the font family used for text with the
+    // altfont attribute.
+    pub fn from_settings() -> StyleOptions {
+        StyleOptions {
+            synthetic: match SETTINGS.get_string("synthetic_styles").as_ref().map(|name| name.as_str()) {
+                Some("always") => SyntheticStyles::Always,
+                Some("never") => SyntheticStyles::Never,
+                _ => SyntheticStyles::Auto
+            },
+            bold_enabled: !SETTINGS.get_bool("disable_bold", false),
+            italic_enabled: !SETTINGS.get_bool("disable_italic", false),
+            altfont: SETTINGS.get_string("altfont").filter(|name| !name.is_empty())
+        }
+    }
+}
+
+// Describes the face a font was loaded from and which styles were faked on top of it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FontVariant {
+    pub bold: bool,
+    pub italic: bool,
+    pub synthetic_bold: bool,
+    pub synthetic_italic: bool
+}
+
+impl FontVariant {
+    pub fn apply_synthetic_style(&self, font: &mut Font) {
+        if self.synthetic_bold {
+            font.set_embolden(true);
+        }
+        if self.synthetic_italic {
+            font.set_skew_x(SYNTHETIC_ITALIC_SKEW);
+        }
+    }
+}
+
+fn is_bold(style: FontStyle) -> bool {
+    *style.weight() >= *Weight::SEMI_BOLD
+}
+
+fn is_italic(style: FontStyle) -> bool {
+    style.slant() != Slant::Upright
+}
+
+// Typeface::new falls back to the default typeface for unknown families, so the family name of
+// the result is checked to make sure the requested font was actually found.
+pub fn load_family_typeface(name: &str, style: FontStyle) -> Option<Typeface> {
+    Typeface::new(name, style).filter(|typeface| typeface.family_name().eq_ignore_ascii_case(name))
+}
+
+// The face is only used when it really is the requested style of the family
+fn load_exact_typeface(name: &str, style: FontStyle) -> Option<Typeface> {
+    load_family_typeface(name, style).filter(|typeface| {
+        let actual_style = typeface.font_style();
+        is_bold(actual_style) == is_bold(style) && is_italic(actual_style) == is_italic(style)
+    })
+}
+
+// Picks the closest face to the requested style, synthesizing whatever the face lacks unless
+// synthesis is disabled. The returned variant records which face was used.
+pub fn load_variant(name: &str, regular: &Typeface, bold: bool, italic: bool, options: &StyleOptions) -> (Typeface, FontVariant) {
+    if !bold && !italic {
+        return (regular.clone(), FontVariant::default());
+    }
+
+    if options.synthetic != SyntheticStyles::Always {
+        let candidates = [(bold, italic), (bold, false), (false, italic)];
+        for &(face_bold, face_italic) in candidates.iter() {
+            if !face_bold && !face_italic {
+                continue;
+            }
+
+            if let Some(typeface) = load_face(name, face_bold, face_italic) {
+                let synthesize = options.synthetic == SyntheticStyles::Auto;
+                return (typeface, FontVariant {
+                    bold: face_bold,
+                    italic: face_italic,
+                    synthetic_bold: synthesize && bold && !face_bold,
+                    synthetic_italic: synthesize && italic && !face_italic
+                });
+            }
+        }
+    }
+
+    let synthesize = options.synthetic != SyntheticStyles::Never;
+    (regular.clone(), FontVariant {
+        bold: false,
+        italic: false,
+        synthetic_bold: synthesize && bold,
+        synthetic_italic: synthesize && italic
+    })
+}
+
+fn load_face(name: &str, bold: bool, italic: bool) -> Option<Typeface> {
+    match (bold, italic) {
+        (false, false) => load_exact_typeface(name, FontStyle::normal()),
+        (true, false) => load_exact_typeface(name, FontStyle::bold()),
+        (false, true) => load_exact_typeface(name, FontStyle::italic()),
+        (true, true) => load_exact_typeface(name, FontStyle::bold_italic())
+    }
+}
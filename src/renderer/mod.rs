@@ -1,71 +1,160 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use skulpin::CoordinateSystemHelper;
-use skulpin::skia_safe::{Canvas, Paint, Surface, Budgeted, Rect, Typeface, Font, FontStyle, colors};
+use std::time::Instant;
+use skulpin::skia_safe::{Canvas, Paint, PaintStyle, Path, DashPathEffect, Surface, Budgeted, Rect, Typeface, Font, FontMgr, FontStyle, colors};
+use skulpin::skia_safe::canvas::SaveLayerRec;
 use skulpin::skia_safe::gpu::SurfaceOrigin;
 
+mod animation_utils;
 mod caching_shaper;
 mod cursor_renderer;
-
-pub use caching_shaper::CachingShaper;
+mod cursor_vfx;
+mod font_features;
+mod font_variant;
+mod fps_tracker;
+mod profiler;
+mod raster_options;
+mod scroll_animation;
+
+pub use caching_shaper::{CachingShaper, ShapingCacheStats};
+pub use raster_options::RasterOptions;
+use raster_options::RASTER_SETTINGS;
 
 use cursor_renderer::CursorRenderer;
-use crate::editor::{Editor, Style, Colors};
+use font_features::{FontFeatureSettings, FONT_FEATURE_SETTINGS};
+use font_variant::{FontVariant, StyleOptions, STYLE_SETTINGS, load_family_typeface, load_variant};
+use profiler::{FrameStats, Profiler};
+use scroll_animation::ScrollAnimator;
+use crate::editor::{Editor, Style, Colors, ScrollRegion, is_emoji_cluster};
+use crate::settings::SETTINGS;
 
 const FONT_NAME: &str = "Delugia Nerd Font";
 const FONT_SIZE: f32 = 14.0;
+const EMOJI_FONT_NAMES: &[&str] = &["Noto Color Emoji", "Apple Color Emoji", "Segoe UI Emoji", "Twemoji", "JoyPixels"];
 
 pub struct Fonts {
     pub name: String,
     pub size: f32,
+    pub style_options: StyleOptions,
     pub normal: Font,
     pub bold: Font,
     pub italic: Font,
-    pub bold_italic: Font
+    pub bold_italic: Font,
+    pub variants: [FontVariant; 4],
+    // Normal, bold, italic and bold italic faces of the altfont family
+    pub alternates: Option<[Font; 4]>
+}
+
+fn load_alternates(name: &str, size: f32) -> Option<[Font; 4]> {
+    let load = |style: FontStyle| load_family_typeface(name, style)
+        .map(|typeface| Font::from_typeface(typeface, size));
+    let alternates = [
+        load(FontStyle::normal())?,
+        load(FontStyle::bold())?,
+        load(FontStyle::italic())?,
+        load(FontStyle::bold_italic())?
+    ];
+    Some(alternates)
 }
 
 impl Fonts {
-    fn new(name: &str, size: f32) -> Fonts {
-        Fonts {
+    fn new(name: &str, size: f32, raster_options: &RasterOptions, style_options: StyleOptions) -> Fonts {
+        let regular = Typeface::new(name, FontStyle::normal()).expect("Could not load normal font file");
+        let (bold, bold_variant) = load_variant(name, &regular, true, false, &style_options);
+        let (italic, italic_variant) = load_variant(name, &regular, false, true, &style_options);
+        let (bold_italic, bold_italic_variant) = load_variant(name, &regular, true, true, &style_options);
+        let alternates = style_options.altfont.as_ref().and_then(|altfont| {
+            let alternates = load_alternates(altfont, size);
+            if alternates.is_none() {
+                println!("Could not load altfont {}", altfont);
+            }
+            alternates
+        });
+
+        let mut fonts = Fonts {
             name: name.to_string(),
             size,
-            normal: Font::from_typeface(
-                Typeface::new(name, FontStyle::normal()).expect("Could not load normal font file"),
-                size),
-            bold: Font::from_typeface(
-                Typeface::new(name, FontStyle::bold()).expect("Could not load bold font file"),
-                size),
-            italic: Font::from_typeface(
-                Typeface::new(name, FontStyle::italic()).expect("Could not load italic font file"),
-                size),
-            bold_italic: Font::from_typeface(
-                Typeface::new(name, FontStyle::bold_italic()).expect("Could not load bold italic font file"),
-                size)
+            style_options,
+            normal: Font::from_typeface(regular, size),
+            bold: Font::from_typeface(bold, size),
+            italic: Font::from_typeface(italic, size),
+            bold_italic: Font::from_typeface(bold_italic, size),
+            variants: [FontVariant::default(), bold_variant, italic_variant, bold_italic_variant],
+            alternates
+        };
+
+        fonts.set_raster_options(raster_options);
+        fonts
+    }
+
+    fn set_raster_options(&mut self, raster_options: &RasterOptions) {
+        let mut fonts = [&mut self.normal, &mut self.bold, &mut self.italic, &mut self.bold_italic];
+        for (font, variant) in fonts.iter_mut().zip(self.variants.iter()) {
+            raster_options.apply(font);
+            variant.apply_synthetic_style(font);
+        }
+        if let Some(alternates) = &mut self.alternates {
+            for font in alternates.iter_mut() {
+                raster_options.apply(font);
+            }
         }
     }
 
-    fn get(&self, style: &Style) -> &Font {
-        match (style.bold, style.italic) {
-            (false, false) => &self.normal,
-            (true, false) => &self.bold,
-            (false, true)  => &self.italic,
-            (true, true) => &self.bold_italic
+    // Returns the font used for the style along with the variant describing which face it was
+    // loaded from and what was synthesized on top of it. Text with the altfont attribute uses
+    // the altfont family when one is loaded and the regular fonts otherwise.
+    pub fn get_with_variant(&self, style: &Style) -> (&Font, FontVariant) {
+        let bold = style.bold && self.style_options.bold_enabled;
+        let italic = style.italic && self.style_options.italic_enabled;
+        let index = match (bold, italic) {
+            (false, false) => 0,
+            (true, false) => 1,
+            (false, true) => 2,
+            (true, true) => 3
+        };
+
+        if let (true, Some(alternates)) = (style.altfont, &self.alternates) {
+            return (&alternates[index], FontVariant { bold, italic, ..FontVariant::default() });
         }
+
+        let fonts = [&self.normal, &self.bold, &self.italic, &self.bold_italic];
+        (fonts[index], self.variants[index])
+    }
+
+    fn get(&self, style: &Style) -> &Font {
+        self.get_with_variant(style).0
     }
 }
 
 pub struct FontLookup {
     pub name: String,
     pub base_size: f32,
-    pub loaded_fonts: HashMap<u16, Fonts>
+    pub raster_options: RasterOptions,
+    pub loaded_fonts: HashMap<u16, Fonts>,
+    emoji_typeface: Option<Typeface>
+}
+
+// Looks for one of the well known color emoji fonts, preferring g:neovide_emoji_font when set
+fn load_emoji_typeface() -> Option<Typeface> {
+    let preferred = SETTINGS.get_string("emoji_font");
+    preferred.iter().map(|name| name.as_str())
+        .chain(EMOJI_FONT_NAMES.iter().cloned())
+        .filter_map(|name| load_family_typeface(name, FontStyle::normal()))
+        .next()
+}
+
+pub fn font_available(name: &str) -> bool {
+    load_family_typeface(name, FontStyle::normal()).is_some()
 }
 
 impl FontLookup {
-    pub fn new(name: &str, base_size: f32) -> FontLookup {
+    pub fn new(name: &str, base_size: f32, raster_options: RasterOptions) -> FontLookup {
         let mut lookup = FontLookup {
             name: name.to_string(),
             base_size,
-            loaded_fonts: HashMap::new()
+            raster_options,
+            loaded_fonts: HashMap::new(),
+            emoji_typeface: load_emoji_typeface()
         };
 
         lookup.size(1);
@@ -78,10 +167,85 @@ impl FontLookup {
     fn size(&mut self, size_multiplier: u16) -> &Fonts {
         let name = self.name.clone();
         let base_size = self.base_size;
+        let raster_options = &self.raster_options;
         self.loaded_fonts.entry(size_multiplier).or_insert_with(|| {
-            Fonts::new(&name, base_size * size_multiplier as f32)
+            Fonts::new(&name, base_size * size_multiplier as f32, raster_options, StyleOptions::from_settings())
         })
     }
+
+    // Uses the color emoji font when it has a glyph for the cluster and otherwise asks the font
+    // manager for any font which can display its first character.
+    fn emoji_typeface(&self, text: &str) -> Option<Typeface> {
+        let first_character = text.chars().next()?;
+        if let Some(typeface) = &self.emoji_typeface {
+            let mut glyphs = [0u16; 1];
+            typeface.str_to_glyphs(&first_character.to_string(), &mut glyphs);
+            if glyphs[0] != 0 {
+                return Some(typeface.clone());
+            }
+        }
+
+        FontMgr::default().match_family_style_character("", FontStyle::normal(), &[], first_character as i32)
+    }
+
+    pub fn set_raster_options(&mut self, raster_options: RasterOptions) {
+        for fonts in self.loaded_fonts.values_mut() {
+            fonts.set_raster_options(&raster_options);
+        }
+        self.raster_options = raster_options;
+    }
+}
+
+// Cell dimensions are snapped to whole device pixels so that every cell boundary lands on a
+// pixel edge and neighboring cells never blur into each other.
+fn measure_cell(fonts_lookup: &mut FontLookup, paint: &Paint) -> (f32, f32) {
+    let base_fonts = fonts_lookup.size(1);
+    let (_, bounds) = base_fonts.normal.measure_str("_", Some(paint));
+    let (_, metrics) = base_fonts.normal.metrics();
+    let font_width = bounds.width().round().max(1.0);
+    let font_height = (metrics.descent - metrics.ascent).ceil().max(1.0);
+    (font_width, font_height)
+}
+
+// Color emoji fonts rarely match the metrics of the main font, so the shaped emoji is scaled
+// to fit the cells neovim allocated for it (one or two depending on 'emoji') and centered.
+pub fn draw_emoji(canvas: &mut Canvas, shaper: &mut CachingShaper, fonts_lookup: &FontLookup, paint: &Paint, text: &str, cell_rect: Rect) {
+    let typeface = match fonts_lookup.emoji_typeface(text) {
+        Some(typeface) => typeface,
+        None => return
+    };
+
+    let font = Font::from_typeface(typeface, cell_rect.height());
+    let blob = shaper.shape_cached(text.to_string(), &font, &[]);
+    let bounds = blob.bounds();
+    if bounds.width() <= 0.0 || bounds.height() <= 0.0 {
+        return;
+    }
+
+    let scale = (cell_rect.width() / bounds.width()).min(cell_rect.height() / bounds.height());
+    let left = cell_rect.left + (cell_rect.width() - bounds.width() * scale) / 2.0 - bounds.left * scale;
+    let top = cell_rect.top + (cell_rect.height() - bounds.height() * scale) / 2.0 - bounds.top * scale;
+
+    canvas.save();
+    canvas.translate((left, top));
+    canvas.scale((scale, scale));
+    // Color glyphs carry their own colors. The foreground color only shows up when the
+    // fallback font turns out to be monochrome.
+    let mut paint = paint.clone();
+    paint.set_anti_alias(true);
+    canvas.draw_text_blob(blob, (0.0, 0.0), &paint);
+    canvas.restore();
+}
+
+fn create_surface(root_canvas: &mut Canvas) -> Surface {
+    let image_info = root_canvas.image_info();
+    if let Some(mut context) = root_canvas.gpu_context() {
+        let budgeted = Budgeted::YES;
+        let surface_origin = SurfaceOrigin::TopLeft;
+        Surface::new_render_target(&mut context, budgeted, &image_info, None, surface_origin, None, None).expect("Could not create surface")
+    } else {
+        Surface::new_raster(&image_info, None, None).expect("Could not create surface")
+    }
 }
 
 pub struct Renderer {
@@ -91,36 +255,74 @@ pub struct Renderer {
     paint: Paint,
     fonts_lookup: FontLookup,
     shaper: CachingShaper,
+    font_features: FontFeatureSettings,
+    settings_generation: u64,
 
+    pub scale_factor: f64,
     pub font_width: f32,
     pub font_height: f32,
     cursor_renderer: CursorRenderer,
+    scroll_animator: ScrollAnimator,
+    profiler: Profiler
 }
 
 impl Renderer {
-    pub fn new(editor: Arc<Mutex<Editor>>) -> Renderer {
+    pub fn new(editor: Arc<Mutex<Editor>>, scale_factor: f64) -> Renderer {
+        Renderer::with_font(editor, scale_factor, FONT_NAME)
+    }
+
+    pub fn with_font(editor: Arc<Mutex<Editor>>, scale_factor: f64, font_name: &str) -> Renderer {
+        if !font_available(font_name) {
+            println!("Could not find the font {}, using the default font", font_name);
+        }
+
         let surface = None;
         let mut paint = Paint::new(colors::WHITE, None);
         paint.set_anti_alias(false);
         
-        let mut fonts_lookup = FontLookup::new(FONT_NAME, FONT_SIZE);
-        let shaper = CachingShaper::new();
-
-        let base_fonts = fonts_lookup.size(1);
-        let (_, bounds) = base_fonts.normal.measure_str("_", Some(&paint));
-        let font_width = bounds.width();
-        let (_, metrics) = base_fonts.normal.metrics();
-        let font_height = metrics.descent - metrics.ascent;
+        let mut fonts_lookup = FontLookup::new(font_name, FONT_SIZE * scale_factor as f32, RasterOptions::from_settings());
+        let mut shaper = CachingShaper::new();
+        let font_features = FontFeatureSettings::new();
+
+        let (font_width, font_height) = measure_cell(&mut fonts_lookup, &paint);
+        shaper.set_cell_width(font_width, fonts_lookup.base_size);
         let cursor_renderer = CursorRenderer::new();
+        let scroll_animator = ScrollAnimator::new();
+        let profiler = Profiler::new();
+
+        // Generation 0 makes the first frame pick up every setting changed since startup
+        let settings_generation = 0;
+
+        Renderer { editor, surface, paint, fonts_lookup, shaper, font_features, settings_generation, scale_factor, font_width, font_height, cursor_renderer, scroll_animator, profiler }
+    }
 
-        Renderer { editor, surface, paint, fonts_lookup, shaper, font_width, font_height, cursor_renderer }
+    // Fonts are rasterized at the physical pixel size, so any change to the scale factor
+    // requires reloading them and throwing away everything shaped or drawn at the old size.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        if (scale_factor - self.scale_factor).abs() < std::f64::EPSILON {
+            return;
+        }
+
+        self.scale_factor = scale_factor;
+        self.reload_fonts();
     }
 
-    fn draw_background(&mut self, canvas: &mut Canvas, text: &str, grid_pos: (u64, u64), size: u16, style: &Option<Style>, default_colors: &Colors) {
+    fn reload_fonts(&mut self) {
+        let font_name = self.fonts_lookup.name.clone();
+        self.fonts_lookup = FontLookup::new(&font_name, FONT_SIZE * self.scale_factor as f32, self.fonts_lookup.raster_options.clone());
+        self.shaper.clear();
+        let (font_width, font_height) = measure_cell(&mut self.fonts_lookup, &self.paint);
+        self.shaper.set_cell_width(font_width, self.fonts_lookup.base_size);
+        self.font_width = font_width;
+        self.font_height = font_height;
+        self.editor.lock().unwrap().redraw_all();
+    }
+
+    fn draw_background(&mut self, canvas: &mut Canvas, cell_width: u64, grid_pos: (u64, u64), size: u16, style: &Option<Style>, default_colors: &Colors) {
         let (grid_x, grid_y) = grid_pos;
         let x = grid_x as f32 * self.font_width;
         let y = grid_y as f32 * self.font_height;
-        let width = text.chars().count() as f32 * self.font_width * size as f32;
+        let width = cell_width as f32 * self.font_width * size as f32;
         let height = self.font_height * size as f32;
         let region = Rect::new(x, y, x + width, y + height);
 
@@ -130,69 +332,190 @@ impl Renderer {
         canvas.draw_rect(region, &self.paint);
     }
 
-    fn draw_foreground(&mut self, canvas: &mut Canvas, text: &str, grid_pos: (u64, u64), size: u16, style: &Option<Style>, default_colors: &Colors) {
+    fn draw_foreground(&mut self, canvas: &mut Canvas, text: &str, cell_width: u64, grid_pos: (u64, u64), size: u16, style: &Option<Style>, under_cursor: bool, default_colors: &Colors) {
         let (grid_x, grid_y) = grid_pos;
         let x = grid_x as f32 * self.font_width;
         let y = grid_y as f32 * self.font_height;
-        let width = text.chars().count() as f32 * self.font_width;
+        let width = cell_width as f32 * self.font_width * size as f32;
 
         let style = style.clone().unwrap_or(Style::new(default_colors.clone()));
+        let (_, metrics) = self.fonts_lookup.size(size).get(&style).metrics();
+        let baseline = y - metrics.ascent;
 
-        if style.underline || style.undercurl {
-            let (_, metrics) = self.fonts_lookup.size(size).get(&style).metrics();
-            let line_position = metrics.underline_position().unwrap();
-
-            self.paint.set_color(style.special(&default_colors).to_color());
-            canvas.draw_line((x, y - line_position + self.font_height), (x + width, y - line_position + self.font_height), &self.paint);
+        if style.underline || style.undercurl || style.underdouble || style.underdotted || style.underdashed {
+            let line_position = baseline + metrics.underline_position().unwrap_or(metrics.descent / 2.0);
+            let thickness = metrics.underline_thickness().unwrap_or(1.0).max(1.0);
+            self.draw_underline(canvas, &style, (x, x + width), line_position, thickness, default_colors);
         }
 
         self.paint.set_color(style.foreground(&default_colors).to_color());
         let text = text.trim_end();
-        if text.len() > 0 {
-            let blob = self.shaper.shape_cached(text.to_string(), size, self.fonts_lookup.size(size).get(&style));
+        if is_emoji_cluster(text) {
+            self.draw_emoji(canvas, text, Rect::new(x, y, x + width, y + self.font_height * size as f32));
+        } else if text.len() > 0 {
+            let features = self.font_features.features_for(&self.fonts_lookup.name, &style, under_cursor);
+            let blob = self.shaper.shape_cached(text.to_string(), self.fonts_lookup.size(size).get(&style), &features);
             canvas.draw_text_blob(blob, (x, y), &self.paint);
         }
+
+        if style.strikethrough {
+            let line_position = baseline + metrics.strikeout_position().unwrap_or(metrics.ascent / 3.0);
+            let thickness = metrics.strikeout_thickness().unwrap_or(1.0).max(1.0);
+            let mut paint = self.paint.clone();
+            paint.set_style(PaintStyle::Stroke);
+            paint.set_stroke_width(thickness);
+            canvas.draw_line((x, line_position), (x + width, line_position), &paint);
+        }
+    }
+
+    fn draw_emoji(&mut self, canvas: &mut Canvas, text: &str, cell_rect: Rect) {
+        draw_emoji(canvas, &mut self.shaper, &self.fonts_lookup, &self.paint, text, cell_rect);
     }
 
-    pub fn draw(&mut self, gpu_canvas: &mut Canvas, coordinate_system_helper: &CoordinateSystemHelper) -> bool {
-        let ((draw_commands, should_clear), default_colors, cursor) = {
+    fn draw_underline(&mut self, canvas: &mut Canvas, style: &Style, horizontal_extent: (f32, f32), line_position: f32, thickness: f32, default_colors: &Colors) {
+        let (left, right) = horizontal_extent;
+        let mut paint = self.paint.clone();
+        paint.set_color(style.special(&default_colors).to_color());
+        paint.set_style(PaintStyle::Stroke);
+        paint.set_stroke_width(thickness);
+
+        if style.undercurl {
+            // The curl is built out of quadratic segments alternating above and below the
+            // underline position. Each segment spans half a cell so that the waves of
+            // neighboring draw commands line up.
+            paint.set_anti_alias(true);
+            let amplitude = thickness * 1.5;
+            let half_wave = self.font_width / 2.0;
+            let mut path = Path::new();
+            path.move_to((left, line_position));
+            let mut segment_start = left;
+            let mut upward = true;
+            while segment_start < right {
+                let segment_end = (segment_start + half_wave).min(right);
+                let control_y = if upward { line_position - amplitude * 2.0 } else { line_position + amplitude * 2.0 };
+                path.quad_to(((segment_start + segment_end) / 2.0, control_y), (segment_end, line_position));
+                segment_start = segment_end;
+                upward = !upward;
+            }
+            canvas.draw_path(&path, &paint);
+        } else if style.underdouble {
+            let gap = thickness * 2.0;
+            canvas.draw_line((left, line_position), (right, line_position), &paint);
+            canvas.draw_line((left, line_position + gap), (right, line_position + gap), &paint);
+        } else {
+            if style.underdotted {
+                paint.set_path_effect(DashPathEffect::new(&[thickness, thickness], 0.0));
+            } else if style.underdashed {
+                paint.set_path_effect(DashPathEffect::new(&[thickness * 3.0, thickness * 2.0], 0.0));
+            }
+            canvas.draw_line((left, line_position), (right, line_position), &paint);
+        }
+    }
+
+    fn draw_drop_indicator(&mut self, canvas: &mut Canvas, physical_size: (f32, f32), default_colors: &Colors) {
+        let (width, height) = physical_size;
+        let border_width = (self.font_width / 2.0).max(2.0);
+        let region = Rect::new(0.0, 0.0, width, height).with_inset((border_width / 2.0, border_width / 2.0));
+
+        let mut paint = self.paint.clone();
+        paint.set_color(default_colors.foreground.clone().unwrap().to_color());
+        paint.set_alpha(40);
+        canvas.draw_rect(region, &paint);
+
+        paint.set_alpha(200);
+        paint.set_style(PaintStyle::Stroke);
+        paint.set_stroke_width(border_width);
+        canvas.draw_rect(region, &paint);
+    }
+
+    // Draws a frame onto root_canvas, which may belong to a gpu backed window surface or to a
+    // cpu raster surface. The offscreen grid surface is created with the same backend.
+    pub fn draw(&mut self, root_canvas: &mut Canvas, physical_size: (f32, f32)) -> bool {
+        let frame_start = Instant::now();
+
+        // Font options are only rebuilt once the settings they are read from change
+        let settings_generation = SETTINGS.generation();
+        if settings_generation != self.settings_generation {
+            if SETTINGS.changed_since(self.settings_generation, RASTER_SETTINGS) {
+                let raster_options = RasterOptions::from_settings();
+                if raster_options != self.fonts_lookup.raster_options {
+                    self.fonts_lookup.set_raster_options(raster_options);
+                    self.shaper.clear();
+                    self.editor.lock().unwrap().redraw_all();
+                }
+            }
+
+            if SETTINGS.changed_since(self.settings_generation, STYLE_SETTINGS)
+                && StyleOptions::from_settings() != self.fonts_lookup.size(1).style_options {
+                self.reload_fonts();
+            }
+
+            if SETTINGS.changed_since(self.settings_generation, FONT_FEATURE_SETTINGS) && self.font_features.update_from_settings() {
+                self.editor.lock().unwrap().redraw_all();
+            }
+
+            self.settings_generation = settings_generation;
+        }
+
+        let (dirty_cell_count, redraw_event_count, (draw_commands, should_clear), default_colors, cursor, scroll_regions) = {
             let mut editor = self.editor.lock().unwrap();
             (
+                editor.dirty.iter().flatten().filter(|dirty| **dirty).count(),
+                editor.redraw_event_count,
                 editor.build_draw_commands(), 
                 editor.default_colors.clone(), 
-                editor.cursor.clone()
+                editor.cursor.clone(),
+                editor.scroll_regions.drain(..).collect::<Vec<ScrollRegion>>()
             )
         };
 
         if should_clear {
             self.surface = None;
+            self.scroll_animator.clear();
         }
 
         let mut surface = self.surface.take().unwrap_or_else(|| {
-            let mut context = gpu_canvas.gpu_context().unwrap();
-            let budgeted = Budgeted::YES;
-            let image_info = gpu_canvas.image_info();
-            let surface_origin = SurfaceOrigin::TopLeft;
-            let mut surface = Surface::new_render_target(&mut context, budgeted, &image_info, None, surface_origin, None, None).expect("Could not create surface");
+            let mut surface = create_surface(root_canvas);
             let canvas = surface.canvas();
             canvas.clear(default_colors.background.clone().unwrap().to_color());
             surface
         });
 
+        if !scroll_regions.is_empty() && self.scroll_animator.enabled() {
+            let previous_contents = surface.image_snapshot();
+            for region in scroll_regions {
+                self.scroll_animator.start(region, previous_contents.clone());
+            }
+        }
+
         let mut canvas = surface.canvas();
-        coordinate_system_helper.use_logical_coordinates(&mut canvas);
 
         for command in draw_commands.iter() {
-            self.draw_background(&mut canvas, &command.text, command.grid_position.clone(), command.scale, &command.style, &default_colors);
+            self.draw_background(&mut canvas, command.cell_width, command.grid_position.clone(), command.scale, &command.style, &default_colors);
         }
+        // Gamma is applied to all of the frame's text at once
+        let gamma_layer_paint = self.fonts_lookup.raster_options.gamma_layer_paint();
+        if let Some(layer_paint) = &gamma_layer_paint {
+            canvas.save_layer(&SaveLayerRec::default().paint(layer_paint));
+        }
+        let (cursor_row, cursor_column) = cursor.position;
         for command in draw_commands.iter() {
-            self.draw_foreground(&mut canvas, &command.text, command.grid_position.clone(), command.scale, &command.style, &default_colors);
+            let (x, y) = command.grid_position;
+            let under_cursor = y == cursor_row && x <= cursor_column && cursor_column < x + command.cell_width;
+            self.draw_foreground(&mut canvas, &command.text, command.cell_width, command.grid_position.clone(), command.scale, &command.style, under_cursor, &default_colors);
+        }
+        if gamma_layer_paint.is_some() {
+            canvas.restore();
         }
 
         let image = surface.image_snapshot();
-        let window_size = coordinate_system_helper.window_logical_size();
-        let image_destination = Rect::new(0.0, 0.0, window_size.width as f32, window_size.height as f32);
-        gpu_canvas.draw_image_rect(image, None, &image_destination, &self.paint);
+        let (width, height) = physical_size;
+        let image_destination = Rect::new(0.0, 0.0, width, height);
+        root_canvas.draw_image_rect(&image, None, &image_destination, &self.paint);
+        let scroll_animating = self.scroll_animator.draw(
+            root_canvas, &image,
+            self.font_width, self.font_height,
+            default_colors.background.clone().unwrap().to_color());
 
         self.surface = Some(surface);
 
@@ -201,8 +524,25 @@ impl Renderer {
             self.font_width, self.font_height, 
             &mut self.paint, self.editor.clone(),
             &mut self.shaper, &mut self.fonts_lookup,
-            gpu_canvas);
+            &self.font_features, root_canvas);
+
+        if self.editor.lock().unwrap().file_hovering {
+            self.draw_drop_indicator(root_canvas, physical_size, &default_colors);
+        }
+
+        // The profiler keeps frames coming while it is shown so that its numbers stay current
+        let profiling = self.profiler.enabled();
+        if profiling {
+            self.profiler.record_frame(FrameStats {
+                draw_duration: frame_start.elapsed(),
+                draw_command_count: draw_commands.len(),
+                dirty_cell_count,
+                redraw_event_count,
+                cache_stats: self.shaper.stats()
+            });
+            self.profiler.draw(root_canvas, &self.fonts_lookup.size(1).normal, self.font_height);
+        }
 
-        draw_commands.len() > 0 || cursor_animating
+        draw_commands.len() > 0 || cursor_animating || scroll_animating || profiling
     }
 }
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use skulpin::skia_safe::{Canvas, Color, Font, Paint, PaintStyle, Path, Rect, TextBlob};
+
+use super::caching_shaper::ShapingCacheStats;
+use super::fps_tracker::FpsTracker;
+use crate::settings::SETTINGS;
+
+const FRAME_TIME_HISTORY: usize = 120;
+const GRAPH_WIDTH: f32 = 240.0;
+const GRAPH_HEIGHT: f32 = 60.0;
+// Frame times at or above this many milliseconds hit the top of the graph
+const GRAPH_MAX_FRAME_TIME: f32 = 33.3;
+const MARGIN: f32 = 8.0;
+
+// What happened during a single call to Renderer::draw
+pub struct FrameStats {
+    pub draw_duration: Duration,
+    pub draw_command_count: usize,
+    pub dirty_cell_count: usize,
+    pub redraw_event_count: u64,
+    pub cache_stats: ShapingCacheStats
+}
+
+pub struct Profiler {
+    fps_tracker: FpsTracker,
+    frame_times: VecDeque<f32>,
+    last_frame: Option<FrameStats>,
+    redraw_event_rate: u64,
+    redraw_events_at_last_sample: u64,
+    last_sample_time: Instant
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            fps_tracker: FpsTracker::new(),
+            frame_times: VecDeque::with_capacity(FRAME_TIME_HISTORY),
+            last_frame: None,
+            redraw_event_rate: 0,
+            redraw_events_at_last_sample: 0,
+            last_sample_time: Instant::now()
+        }
+    }
+
+    // Toggled with g:neovide_profiler, for example from a mapping like
+    // nnoremap <F3> :let g:neovide_profiler = !get(g:, 'neovide_profiler')<CR>
+    pub fn enabled(&self) -> bool {
+        SETTINGS.get_bool("profiler", false)
+    }
+
+    pub fn record_frame(&mut self, stats: FrameStats) {
+        self.fps_tracker.record_frame();
+
+        if self.frame_times.len() == FRAME_TIME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(stats.draw_duration.as_secs_f32() * 1000.0);
+
+        let elapsed = self.last_sample_time.elapsed().as_secs_f32();
+        if elapsed >= 1.0 {
+            let events = stats.redraw_event_count.saturating_sub(self.redraw_events_at_last_sample);
+            self.redraw_event_rate = (events as f32 / elapsed) as u64;
+            self.redraw_events_at_last_sample = stats.redraw_event_count;
+            self.last_sample_time = Instant::now();
+        }
+
+        self.last_frame = Some(stats);
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, font: &Font, line_height: f32) {
+        let stats = match &self.last_frame {
+            Some(stats) => stats,
+            None => return
+        };
+
+        let lines = [
+            format!("FPS: {}", self.fps_tracker.fps),
+            format!("Frame: {:.2}ms", stats.draw_duration.as_secs_f32() * 1000.0),
+            format!("Draw commands: {}", stats.draw_command_count),
+            format!("Dirty cells: {}", stats.dirty_cell_count),
+            format!("Shaping cache: {:.1}% hits, {} entries", stats.cache_stats.hit_rate() * 100.0, stats.cache_stats.entries),
+            format!("Redraw events: {}/s", self.redraw_event_rate)
+        ];
+
+        let width = GRAPH_WIDTH + MARGIN * 2.0;
+        let height = line_height * lines.len() as f32 + GRAPH_HEIGHT + MARGIN * 3.0;
+        let mut paint = Paint::new(Color::from_argb(200, 0, 0, 0).into(), None);
+        canvas.draw_rect(Rect::from_xywh(0.0, 0.0, width, height), &paint);
+
+        paint.set_anti_alias(true);
+        paint.set_color(Color::WHITE);
+        let (_, metrics) = font.metrics();
+        let mut y = MARGIN;
+        for line in lines.iter() {
+            if let Some(blob) = TextBlob::from_str(line, font) {
+                canvas.draw_text_blob(&blob, (MARGIN, y - metrics.ascent), &paint);
+            }
+            y += line_height;
+        }
+
+        let graph = Rect::from_xywh(MARGIN, y + MARGIN, GRAPH_WIDTH, GRAPH_HEIGHT);
+        paint.set_style(PaintStyle::Stroke);
+        paint.set_stroke_width(1.0);
+        paint.set_color(Color::from_argb(255, 80, 80, 80));
+        canvas.draw_rect(graph, &paint);
+
+        // A line at 16.7ms marks the budget of a 60hz display
+        let budget_y = graph.bottom - graph.height() * (16.7 / GRAPH_MAX_FRAME_TIME);
+        canvas.draw_line((graph.left, budget_y), (graph.right, budget_y), &paint);
+
+        let mut path = Path::new();
+        let step = GRAPH_WIDTH / (FRAME_TIME_HISTORY - 1) as f32;
+        for (index, frame_time) in self.frame_times.iter().enumerate() {
+            let x = graph.left + index as f32 * step;
+            let y = graph.bottom - graph.height() * (frame_time / GRAPH_MAX_FRAME_TIME).min(1.0);
+            if index == 0 {
+                path.move_to((x, y));
+            } else {
+                path.line_to((x, y));
+            }
+        }
+        paint.set_color(Color::GREEN);
+        canvas.draw_path(&path, &paint);
+    }
+}
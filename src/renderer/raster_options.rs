@@ -0,0 +1,109 @@
+use skulpin::skia_safe::{Canvas, Font, FontHinting, Paint, Point, TextBlob, table_color_filter};
+use skulpin::skia_safe::canvas::SaveLayerRec;
+use skulpin::skia_safe::font::Edging;
+
+use crate::settings::SETTINGS;
+
+// The settings RasterOptions::from_settings reads
+pub const RASTER_SETTINGS: &[&str] = &["font_edging", "font_hinting", "font_subpixel", "font_embolden", "font_gamma"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RasterOptions {
+    pub edging: Edging,
+    pub hinting: FontHinting,
+    pub subpixel: bool,
+    pub embolden: bool,
+    pub gamma: f32
+}
+
+fn parse_edging(name: &str) -> Option<Edging> {
+    match name {
+        "alias" => Some(Edging::Alias),
+        "antialias" => Some(Edging::AntiAlias),
+        "subpixelantialias" => Some(Edging::SubpixelAntiAlias),
+        _ => None
+    }
+}
+
+fn parse_hinting(name: &str) -> Option<FontHinting> {
+    match name {
+        "none" => Some(FontHinting::None),
+        "slight" => Some(FontHinting::Slight),
+        "normal" => Some(FontHinting::Normal),
+        "full" => Some(FontHinting::Full),
+        _ => None
+    }
+}
+
+impl RasterOptions {
+    pub fn new() -> RasterOptions {
+        RasterOptions {
+            edging: Edging::AntiAlias,
+            hinting: FontHinting::Full,
+            subpixel: false,
+            embolden: false,
+            gamma: 1.0
+        }
+    }
+
+    // Reads g:neovide_font_edging, g:neovide_font_hinting, g:neovide_font_subpixel,
+    // g:neovide_font_embolden and g:neovide_font_gamma, falling back to the defaults for missing
+    // or invalid values.
+    pub fn from_settings() -> RasterOptions {
+        let defaults = RasterOptions::new();
+        RasterOptions {
+            edging: SETTINGS.get_string("font_edging")
+                .and_then(|name| parse_edging(&name.to_lowercase()))
+                .unwrap_or(defaults.edging),
+            hinting: SETTINGS.get_string("font_hinting")
+                .and_then(|name| parse_hinting(&name.to_lowercase()))
+                .unwrap_or(defaults.hinting),
+            subpixel: SETTINGS.get_bool("font_subpixel", defaults.subpixel),
+            embolden: SETTINGS.get_bool("font_embolden", defaults.embolden),
+            gamma: SETTINGS.get_f32("font_gamma", defaults.gamma).max(0.1).min(4.0)
+        }
+    }
+
+    pub fn apply(&self, font: &mut Font) {
+        font.set_edging(self.edging);
+        font.set_hinting(self.hinting);
+        font.set_subpixel(self.subpixel);
+        font.set_embolden(self.embolden);
+    }
+
+    // Skia's glyph gamma is fixed when it is built, so gamma is applied to the coverage of the
+    // drawn glyphs instead. Text is drawn into a layer with this paint, which remaps the alpha
+    // on the way back. Values above 1 thicken text and values below 1 thin it. Layers aren't
+    // opaque, so subpixel antialiased text falls back to grayscale while gamma is set. Returns
+    // None when gamma is 1 and no layer is needed.
+    pub fn gamma_layer_paint(&self) -> Option<Paint> {
+        if (self.gamma - 1.0).abs() < std::f32::EPSILON {
+            return None;
+        }
+
+        let mut coverage = [0u8; 256];
+        for (index, value) in coverage.iter_mut().enumerate() {
+            *value = ((index as f32 / 255.0).powf(1.0 / self.gamma) * 255.0).round() as u8;
+        }
+        let mut layer_paint = Paint::default();
+        layer_paint.set_color_filter(table_color_filter::from_argb(Some(&coverage), None, None, None));
+        Some(layer_paint)
+    }
+
+    // Draws a single blob with gamma applied. The grid draws all of its text inside one layer
+    // instead, see Renderer::draw.
+    pub fn draw_text_blob(&self, canvas: &mut Canvas, blob: &TextBlob, origin: impl Into<Point>, paint: &Paint) {
+        let origin = origin.into();
+        match self.gamma_layer_paint() {
+            Some(layer_paint) => {
+                let bounds = blob.bounds().with_offset(origin);
+                canvas.save_layer(&SaveLayerRec::default().bounds(&bounds).paint(&layer_paint));
+                canvas.draw_text_blob(blob, origin, paint);
+                canvas.restore();
+            },
+            None => {
+                canvas.draw_text_blob(blob, origin, paint);
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Instant;
+
+use skulpin::skia_safe::{Canvas, Color, Image, Paint, Rect};
+use skulpin::skia_safe::canvas::SrcRectConstraint;
+
+use super::animation_utils::{Easing, lerp};
+use crate::editor::ScrollRegion;
+use crate::settings::SETTINGS;
+
+const DEFAULT_SCROLL_ANIMATION_LENGTH: f32 = 0.3;
+
+pub struct ScrollAnimation {
+    region: ScrollRegion,
+    previous_contents: Image,
+    start_time: Instant,
+    start_offset: f32
+}
+
+impl ScrollAnimation {
+    fn progress(&self, length: f32, easing: Easing) -> f32 {
+        if length <= 0.0 {
+            return 1.0;
+        }
+        easing.ease(self.start_time.elapsed().as_secs_f32() / length)
+    }
+
+    // Offset in rows of the new contents relative to their final position
+    fn current_offset(&self, length: f32, easing: Easing) -> f32 {
+        lerp(self.start_offset, 0.0, self.progress(length, easing))
+    }
+}
+
+pub struct ScrollAnimator {
+    animations: Vec<ScrollAnimation>
+}
+
+fn animation_length() -> f32 {
+    SETTINGS.get_f32("scroll_animation_length", DEFAULT_SCROLL_ANIMATION_LENGTH)
+}
+
+fn animation_easing() -> Easing {
+    SETTINGS.get_string("scroll_animation_easing")
+        .and_then(|name| Easing::from_name(&name))
+        .unwrap_or(Easing::Cubic)
+}
+
+impl ScrollAnimator {
+    pub fn new() -> ScrollAnimator {
+        ScrollAnimator {
+            animations: Vec::new()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        animation_length() > 0.0
+    }
+
+    pub fn clear(&mut self) {
+        self.animations.clear();
+    }
+
+    // Starts animating a scroll of the given region. The previous contents must be captured
+    // before the draw commands produced by the scroll are applied.
+    pub fn start(&mut self, region: ScrollRegion, previous_contents: Image) {
+        let length = animation_length();
+        let easing = animation_easing();
+
+        // A scroll that lands on a region which is still animating continues from wherever the
+        // running animation currently is so that repeated scrolling doesn't jump.
+        let mut start_offset = region.rows as f32;
+        if let Some(index) = self.animations.iter().position(|animation| animation.region.same_area(&region)) {
+            let existing = self.animations.remove(index);
+            start_offset += existing.current_offset(length, easing);
+        }
+
+        self.animations.push(ScrollAnimation {
+            region,
+            previous_contents,
+            start_time: Instant::now(),
+            start_offset
+        });
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas, current_contents: &Image, font_width: f32, font_height: f32, background: Color) -> bool {
+        let length = animation_length();
+        let easing = animation_easing();
+        self.animations.retain(|animation| animation.progress(length, easing) < 1.0);
+
+        let mut paint = Paint::default();
+        for animation in self.animations.iter() {
+            let ScrollRegion { top, bottom, left, right, .. } = animation.region;
+            let region_rect = Rect::new(
+                left as f32 * font_width, top as f32 * font_height,
+                right as f32 * font_width, bottom as f32 * font_height);
+            let offset = animation.current_offset(length, easing) * font_height;
+            let previous_offset = offset - animation.region.rows as f32 * font_height;
+
+            canvas.save();
+            canvas.clip_rect(&region_rect, None, Some(false));
+            paint.set_color(background);
+            canvas.draw_rect(region_rect, &paint);
+
+            // Only the region is taken from each image, so rows outside of it such as the
+            // statusline never slide in. The previous region slides out while the current region
+            // slides in from the other side. Rows which exist in both line up exactly where the
+            // two overlap, and the rows which scrolled off stay visible in the part the current
+            // region has not reached yet.
+            let source = Some((&region_rect, SrcRectConstraint::Strict));
+            canvas.draw_image_rect(&animation.previous_contents, source, &region_rect.with_offset((0.0, previous_offset)), &paint);
+            canvas.draw_image_rect(current_contents, source, &region_rect.with_offset((0.0, offset)), &paint);
+            canvas.restore();
+        }
+
+        !self.animations.is_empty()
+    }
+}
@@ -0,0 +1,391 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use skulpin::skia_safe::{icu, EncodedImageFormat, Image, Surface};
+
+use crate::editor::Editor;
+use crate::renderer::Renderer;
+
+// Neovim tends to send several flushes while starting up, so the screenshot is only taken once
+// no flush has arrived for this long.
+const QUIET_PERIOD: Duration = Duration::from_millis(500);
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+const MAX_SETTLE_FRAMES: usize = 120;
+
+fn wait_for_quiet_period(editor: &Arc<Mutex<Editor>>) {
+    let start = Instant::now();
+    loop {
+        thread::sleep(FRAME_INTERVAL);
+        let last_flush = editor.lock().unwrap().last_flush;
+        match last_flush {
+            Some(last_flush) if last_flush.elapsed() >= QUIET_PERIOD => return,
+            _ if start.elapsed() >= STARTUP_TIMEOUT => {
+                println!("Timed out waiting for neovim to finish drawing");
+                return;
+            },
+            _ => {}
+        }
+    }
+}
+
+// Renders the editor into a cpu raster surface. Animations are run to completion first so that
+// the output only depends on the editor contents.
+fn render_frame(renderer: &mut Renderer, grid_size: (u64, u64)) -> Image {
+    let (grid_width, grid_height) = grid_size;
+    let width = (grid_width as f32 * renderer.font_width) as i32;
+    let height = (grid_height as f32 * renderer.font_height) as i32;
+    let mut surface = Surface::new_raster_n32_premul((width, height)).expect("Could not create raster surface");
+
+    for _ in 0..MAX_SETTLE_FRAMES {
+        if !renderer.draw(surface.canvas(), (width as f32, height as f32)) {
+            break;
+        }
+        thread::sleep(FRAME_INTERVAL);
+    }
+
+    surface.image_snapshot()
+}
+
+// Renders the editor once neovim has finished drawing and writes the result to path as a png
+pub fn take_screenshot(editor: Arc<Mutex<Editor>>, path: &str, grid_size: (u64, u64)) {
+    icu::init();
+    let mut renderer = Renderer::new(editor.clone(), 1.0);
+    wait_for_quiet_period(&editor);
+
+    let image = render_frame(&mut renderer, grid_size);
+    let data = image.encode_to_data(EncodedImageFormat::PNG).expect("Could not encode screenshot");
+    fs::write(path, data.as_bytes()).expect("Could not write screenshot");
+}
+
+// Rendering tests. Scenes are fed through the same redraw event parsing neovim's output goes
+// through and rendered with DejaVu Sans Mono, which most systems ship. Each scene is checked by
+// probing the pixels where decorations and the cursor have to be, which holds on any machine
+// with the font, and compared against its png in golden/ when there is one. Golden images
+// depend on how the machine rasterizes glyphs, so they are written by running the tests with
+// NEOVIDE_UPDATE_GOLDEN=1 on the reference machine and checked in.
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use rmpv::Value;
+    use skulpin::skia_safe::{AlphaType, CachingHint, ColorType, Data, ImageInfo, IPoint};
+
+    use super::*;
+    use crate::events::parse_redraw_event;
+    use crate::renderer::font_available;
+
+    const TEST_FONT: &str = "DejaVu Sans Mono";
+    const GRID_SIZE: (u64, u64) = (24, 8);
+    // Allows for small differences in anti aliasing between skia builds
+    const GOLDEN_TOLERANCE: i32 = 8;
+    // Probes look for solid colors, so only the edges of anti aliased shapes fall outside this
+    const PROBE_TOLERANCE: i32 = 24;
+
+    const FOREGROUND: u32 = 0xd0d0d0;
+    const BACKGROUND: u32 = 0x202020;
+    const SPECIAL: u32 = 0xff4040;
+    const CURL: u32 = 0x40a0ff;
+
+    fn event(name: &str, calls: Vec<Vec<Value>>) -> Value {
+        let mut event = vec![Value::from(name)];
+        event.extend(calls.into_iter().map(Value::from));
+        Value::from(event)
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(entries.into_iter().map(|(key, value)| (Value::from(key), value)).collect())
+    }
+
+    // Wide characters are followed by an empty cell, the way neovim sends them
+    fn grid_line_cells(row: u64, cells: &[&str], highlight_id: u64) -> Vec<Value> {
+        let cells = cells.iter()
+            .enumerate()
+            .map(|(index, text)| {
+                let mut cell = vec![Value::from(*text)];
+                if index == 0 {
+                    cell.push(Value::from(highlight_id));
+                }
+                Value::from(cell)
+            })
+            .collect::<Vec<Value>>();
+        vec![Value::from(1), Value::from(row), Value::from(0), Value::from(cells)]
+    }
+
+    fn grid_line(row: u64, text: &str, highlight_id: u64) -> Vec<Value> {
+        let cells = text.chars().map(|character| character.to_string()).collect::<Vec<String>>();
+        grid_line_cells(row, &cells.iter().map(String::as_str).collect::<Vec<&str>>(), highlight_id)
+    }
+
+    fn hl_attr_define(id: u64, attributes: Vec<(&str, Value)>) -> Vec<Value> {
+        vec![Value::from(id), map(attributes), map(Vec::new()), Value::Array(Vec::new())]
+    }
+
+    fn cursor_goto(row: u64, column: u64) -> Value {
+        event("grid_cursor_goto", vec![vec![Value::from(1), Value::from(row), Value::from(column)]])
+    }
+
+    struct Frame {
+        image: Image,
+        pixels: Vec<u8>,
+        width: usize,
+        cell_width: f32,
+        cell_height: f32
+    }
+
+    impl Frame {
+        fn color(&self, x: usize, y: usize) -> u32 {
+            let index = (y * self.width + x) * 4;
+            let channels = &self.pixels[index..index + 3];
+            (channels[0] as u32) << 16 | (channels[1] as u32) << 8 | channels[2] as u32
+        }
+
+        fn is_near(&self, x: usize, y: usize, expected: u32) -> bool {
+            let actual = self.color(x, y);
+            [16, 8, 0].iter().all(|shift| {
+                ((actual >> shift & 0xff) as i32 - (expected >> shift & 0xff) as i32).abs() <= PROBE_TOLERANCE
+            })
+        }
+
+        // Pixel bounds of a run of cells
+        fn cells(&self, row: u64, columns: std::ops::Range<u64>) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+            let left = (columns.start as f32 * self.cell_width) as usize;
+            let right = (columns.end as f32 * self.cell_width) as usize;
+            let top = (row as f32 * self.cell_height) as usize;
+            let bottom = ((row + 1) as f32 * self.cell_height) as usize;
+            (left..right, top..bottom)
+        }
+
+        fn coverage(&self, xs: std::ops::Range<usize>, ys: std::ops::Range<usize>, color: u32) -> f32 {
+            let total = xs.len() * ys.len();
+            let matching = ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+                .filter(|&(x, y)| self.is_near(x, y, color))
+                .count();
+            matching as f32 / total.max(1) as f32
+        }
+
+        // For every pixel row of the cells, the share of pixels close to color
+        fn line_coverages(&self, row: u64, columns: std::ops::Range<u64>, color: u32) -> Vec<f32> {
+            let (xs, ys) = self.cells(row, columns);
+            ys.map(|y| self.coverage(xs.clone(), y..y + 1, color)).collect()
+        }
+    }
+
+    // Returns None when the test font is missing, in which case the calling test is skipped
+    fn render_scene(events: Vec<Value>) -> Option<Frame> {
+        if !font_available(TEST_FONT) {
+            println!("Skipping rendering test, {} is not installed", TEST_FONT);
+            return None;
+        }
+
+        icu::init();
+        let (width, height) = GRID_SIZE;
+        let editor = Arc::new(Mutex::new(Editor::new(width, height)));
+        let setup = vec![
+            event("default_colors_set", vec![vec![
+                Value::from(FOREGROUND), Value::from(BACKGROUND), Value::from(SPECIAL), Value::from(0), Value::from(0)]]),
+            event("grid_resize", vec![vec![Value::from(1), Value::from(width), Value::from(height)]])
+        ];
+        {
+            let mut editor = editor.lock().unwrap();
+            for value in setup.into_iter().chain(events) {
+                for redraw_event in parse_redraw_event(value).expect("Invalid redraw event") {
+                    editor.handle_redraw_event(redraw_event);
+                }
+            }
+        }
+
+        let mut renderer = Renderer::with_font(editor, 1.0, TEST_FONT);
+        let image = render_frame(&mut renderer, GRID_SIZE);
+        let pixels = rgba_pixels(&image);
+        Some(Frame {
+            width: image.width() as usize,
+            image,
+            pixels,
+            cell_width: renderer.font_width,
+            cell_height: renderer.font_height
+        })
+    }
+
+    fn rgba_pixels(image: &Image) -> Vec<u8> {
+        let info = ImageInfo::new(image.dimensions(), ColorType::RGBA8888, AlphaType::Premul, None);
+        let row_bytes = info.min_row_bytes();
+        let mut pixels = vec![0u8; row_bytes * image.height() as usize];
+        assert!(image.read_pixels(&info, pixels.as_mut_slice(), row_bytes, IPoint::new(0, 0), CachingHint::Disallow));
+        pixels
+    }
+
+    fn assert_matches_golden(frame: &Frame, name: &str) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "golden", &format!("{}.png", name)].iter().collect();
+        if env::var_os("NEOVIDE_UPDATE_GOLDEN").is_some() {
+            let data = frame.image.encode_to_data(EncodedImageFormat::PNG).expect("Could not encode image");
+            fs::create_dir_all(path.parent().unwrap()).expect("Could not create golden directory");
+            fs::write(&path, data.as_bytes()).expect("Could not write golden image");
+            return;
+        }
+
+        let golden_bytes = match fs::read(&path) {
+            Ok(golden_bytes) => golden_bytes,
+            Err(_) => {
+                println!("No golden image at {}, only the pixel probes were checked", path.display());
+                return;
+            }
+        };
+        let golden = Image::from_encoded(Data::new_copy(&golden_bytes), None).expect("Could not decode golden image");
+        assert_eq!(frame.image.dimensions(), golden.dimensions(), "Size differs from {}", path.display());
+
+        let differing_pixels = frame.pixels.chunks(4)
+            .zip(rgba_pixels(&golden).chunks(4))
+            .filter(|(actual, expected)| actual.iter()
+                .zip(expected.iter())
+                .any(|(actual, expected)| (*actual as i32 - *expected as i32).abs() > GOLDEN_TOLERANCE))
+            .count();
+        assert_eq!(differing_pixels, 0, "{} pixels differ from {}", differing_pixels, path.display());
+    }
+
+    // Counts the separate bands of pixel rows that are almost fully covered
+    fn solid_lines(coverages: &[f32]) -> usize {
+        let mut lines = 0;
+        let mut previous_solid = false;
+        for coverage in coverages {
+            let solid = *coverage > 0.95;
+            if solid && !previous_solid {
+                lines += 1;
+            }
+            previous_solid = solid;
+        }
+        lines
+    }
+
+    fn max_coverage(coverages: &[f32]) -> f32 {
+        coverages.iter().cloned().fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn decorations() {
+        let frame = match render_scene(vec![
+            event("hl_attr_define", vec![
+                hl_attr_define(1, vec![("underline", Value::from(true))]),
+                hl_attr_define(2, vec![("undercurl", Value::from(true)), ("special", Value::from(CURL))]),
+                hl_attr_define(3, vec![("strikethrough", Value::from(true))]),
+                hl_attr_define(4, vec![("underdouble", Value::from(true))]),
+                hl_attr_define(5, vec![("underdotted", Value::from(true))]),
+                hl_attr_define(6, vec![("underdashed", Value::from(true))]),
+                hl_attr_define(7, vec![("bold", Value::from(true)), ("italic", Value::from(true))])
+            ]),
+            event("grid_line", vec![
+                grid_line(0, "underline", 1),
+                grid_line(1, "undercurl", 2),
+                grid_line(2, "strikethrough", 3),
+                grid_line(3, "underdouble", 4),
+                grid_line(4, "underdotted", 5),
+                grid_line(5, "underdashed", 6),
+                grid_line(6, "bold italic", 7)
+            ]),
+            cursor_goto(7, 23)
+        ]) {
+            Some(frame) => frame,
+            None => return
+        };
+
+        // Underlines are drawn in the special color across the whole run
+        assert_eq!(solid_lines(&frame.line_coverages(0, 0..9, SPECIAL)), 1, "underline");
+        assert_eq!(solid_lines(&frame.line_coverages(3, 0..11, SPECIAL)), 2, "underdouble");
+        for &(row, length, name) in [(4, 11, "underdotted"), (5, 11, "underdashed")].iter() {
+            let coverages = frame.line_coverages(row, 0..length, SPECIAL);
+            let coverage = max_coverage(&coverages);
+            assert!(coverage > 0.3 && coverage < 0.8, "{} covers {} of its line", name, coverage);
+        }
+
+        // The curl is continuous and spans several pixel rows
+        let (xs, ys) = frame.cells(1, 0..9);
+        let curl_columns = xs.clone()
+            .filter(|&x| ys.clone().any(|y| frame.is_near(x, y, CURL)))
+            .count();
+        assert!(curl_columns as f32 / xs.len() as f32 > 0.9, "undercurl has gaps");
+        let curl_rows = ys.clone()
+            .filter(|&y| xs.clone().any(|x| frame.is_near(x, y, CURL)))
+            .count();
+        assert!(curl_rows >= 3, "undercurl is flat");
+
+        // Strikethrough crosses the gaps between letters in the foreground color
+        assert_eq!(solid_lines(&frame.line_coverages(2, 0..13, FOREGROUND)), 1, "strikethrough");
+
+        // Nothing is drawn past the end of the text
+        let (xs, ys) = frame.cells(0, 12..23);
+        assert_eq!(frame.coverage(xs, ys, BACKGROUND), 1.0);
+
+        assert_matches_golden(&frame, "decorations");
+    }
+
+    #[test]
+    fn block_cursor() {
+        let frame = match render_scene(vec![
+            event("hl_attr_define", vec![hl_attr_define(1, vec![("bold", Value::from(true))])]),
+            event("grid_line", vec![grid_line(2, "cursor on bold", 1)]),
+            cursor_goto(2, 10)
+        ]) {
+            Some(frame) => frame,
+            None => return
+        };
+
+        // Without a cursor highlight the colors of the cell are swapped, so the cell is filled
+        // with the foreground and the character is cut out of it in the background color
+        let (xs, ys) = frame.cells(2, 10..11);
+        let filled = frame.coverage(xs.clone(), ys.clone(), FOREGROUND);
+        let character = frame.coverage(xs, ys, BACKGROUND);
+        assert!(filled > 0.5, "cursor covers {} of its cell", filled);
+        assert!(character > 0.05, "character under the cursor covers {} of the cell", character);
+
+        assert_matches_golden(&frame, "block_cursor");
+    }
+
+    #[test]
+    fn wide_block_cursor() {
+        let frame = match render_scene(vec![
+            event("grid_line", vec![grid_line_cells(3, &["w", "i", "d", "e", " ", "漢", "", "字", ""], 0)]),
+            cursor_goto(3, 5)
+        ]) {
+            Some(frame) => frame,
+            None => return
+        };
+
+        for column in 5..7 {
+            let (xs, ys) = frame.cells(3, column..column + 1);
+            let filled = frame.coverage(xs, ys, FOREGROUND);
+            assert!(filled > 0.5, "cursor covers {} of cell {}", filled, column);
+        }
+        let (xs, ys) = frame.cells(3, 7..8);
+        assert!(frame.coverage(xs, ys, FOREGROUND) < 0.5, "cursor extends past the wide character");
+
+        assert_matches_golden(&frame, "wide_block_cursor");
+    }
+
+    #[test]
+    fn vertical_cursor() {
+        let frame = match render_scene(vec![
+            event("mode_info_set", vec![vec![Value::from(true), Value::from(vec![
+                map(vec![("cursor_shape", Value::from("vertical")), ("cell_percentage", Value::from(25))])
+            ])]]),
+            event("mode_change", vec![vec![Value::from("insert"), Value::from(0)]]),
+            event("grid_line", vec![grid_line(2, "clipped cursor", 0)]),
+            cursor_goto(2, 7)
+        ]) {
+            Some(frame) => frame,
+            None => return
+        };
+
+        // The cursor sits on a space, so only its bar is drawn in the cell
+        let (xs, ys) = frame.cells(2, 7..8);
+        let bar_width = ((xs.len() as f32 * 0.25) as usize).max(1);
+        let bar = frame.coverage(xs.start..xs.start + bar_width, ys.clone(), FOREGROUND);
+        let rest = frame.coverage(xs.start + bar_width + 1..xs.end, ys, BACKGROUND);
+        assert!(bar > 0.9, "bar covers {} of its part of the cell", bar);
+        assert!(rest > 0.95, "bar spills into {} of the rest of the cell", 1.0 - rest);
+
+        assert_matches_golden(&frame, "vertical_cursor");
+    }
+}
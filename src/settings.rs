@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rmpv::Value;
+use neovim_lib::{Neovim, NeovimApi};
+
+const SETTING_PREFIX: &str = "neovide_";
+
+// The id of neovim's channel to us, which rpcrequest and rpcnotify calls from vimscript need
+pub fn channel_id(nvim: &mut Neovim) -> Option<u64> {
+    nvim.get_api_info().ok()
+        .and_then(|info| info.get(0).and_then(|channel| channel.as_u64()))
+}
+
+lazy_static! {
+    pub static ref SETTINGS: Settings = Settings::new();
+}
+
+// Settings are read from global variables prefixed with `neovide_` (so `g:neovide_scale_factor`
+// is looked up as "scale_factor"). A dictionary watcher installed at startup notifies us through
+// the "setting_changed" rpc notification whenever one of them is modified.
+//
+// Every change bumps a generation counter and records it for the changed setting, which lets
+// consumers skip rebuilding state derived from settings which didn't change, see changed_since.
+pub struct Settings {
+    values: RwLock<HashMap<String, Value>>,
+    changed_generations: RwLock<HashMap<String, u64>>,
+    generation: AtomicU64
+}
+
+impl Settings {
+    fn new() -> Settings {
+        Settings {
+            values: RwLock::new(HashMap::new()),
+            changed_generations: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0)
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    // Returns true if any of the named settings changed after the given generation
+    pub fn changed_since(&self, generation: u64, names: &[&str]) -> bool {
+        let changed_generations = self.changed_generations.read().unwrap();
+        names.iter().any(|name| changed_generations.get(*name).map(|changed| *changed > generation).unwrap_or(false))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.values.read().unwrap().get(name).cloned()
+    }
+
+    pub fn get_f32(&self, name: &str, default: f32) -> f32 {
+        match self.get(name) {
+            Some(Value::F32(value)) => value,
+            Some(Value::F64(value)) => value as f32,
+            Some(Value::Integer(value)) => value.as_f64().map(|value| value as f32).unwrap_or(default),
+            _ => default
+        }
+    }
+
+    pub fn get_u64(&self, name: &str, default: u64) -> u64 {
+        match self.get(name) {
+            Some(Value::Integer(value)) => value.as_u64().unwrap_or(default),
+            Some(Value::F32(value)) if value >= 0.0 => value as u64,
+            Some(Value::F64(value)) if value >= 0.0 => value as u64,
+            _ => default
+        }
+    }
+
+    pub fn get_bool(&self, name: &str, default: bool) -> bool {
+        match self.get(name) {
+            Some(Value::Boolean(value)) => value,
+            // Vimscript has no real booleans, so v:true and 1 are both accepted
+            Some(Value::Integer(value)) => value.as_i64().map(|value| value != 0).unwrap_or(default),
+            _ => default
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<String> {
+        match self.get(name) {
+            Some(Value::String(value)) => value.into_str(),
+            _ => None
+        }
+    }
+
+    pub fn set(&self, name: &str, value: Value) {
+        let mut values = self.values.write().unwrap();
+        if let Value::Nil = value {
+            values.remove(name);
+        } else {
+            values.insert(name.to_string(), value);
+        }
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.changed_generations.write().unwrap().insert(name.to_string(), generation);
+    }
+
+    // Changes a setting here and in neovim, so that the global variable stays in sync with
+    // settings changed from the gui or the command line
+    pub fn set_in_nvim(&self, nvim: &mut Neovim, name: &str, value: Value) {
+        self.set(name, value.clone());
+        if let Err(error) = nvim.set_var(&format!("{}{}", SETTING_PREFIX, name), value) {
+            println!("Could not set {}{}: {}", SETTING_PREFIX, name, error);
+        }
+    }
+
+    pub fn read_initial_values(&self, nvim: &mut Neovim) {
+        let filter = format!("filter(copy(g:), 'v:key =~# \"^{}\"')", SETTING_PREFIX);
+        match nvim.eval(&filter) {
+            Ok(Value::Map(entries)) => {
+                for (name, value) in entries {
+                    if let Some(name) = name.as_str() {
+                        self.set(name.trim_start_matches(SETTING_PREFIX), value);
+                    }
+                }
+            },
+            Ok(_) => {},
+            Err(error) => println!("Could not read settings: {}", error)
+        }
+    }
+
+    pub fn setup_change_listener(&self, nvim: &mut Neovim) {
+        // Channel 0 broadcasts to every channel, which still reaches us
+        let channel = channel_id(nvim).unwrap_or(0);
+        let watcher = format!(
+            "call dictwatcheradd(g:, '{}*', {{dict, key, change -> rpcnotify({}, 'setting_changed', key, get(change, 'new', v:null))}})",
+            SETTING_PREFIX, channel);
+        if let Err(error) = nvim.command(&watcher) {
+            println!("Could not listen for setting changes: {}", error);
+        }
+    }
+
+    pub fn handle_changed_notification(&self, arguments: Vec<Value>) {
+        if let [name, value] = arguments.as_slice() {
+            if let Some(name) = name.as_str() {
+                self.set(name.trim_start_matches(SETTING_PREFIX), value.clone());
+            }
+        } else {
+            println!("Invalid setting_changed notification: {:?}", arguments);
+        }
+    }
+}
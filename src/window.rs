@@ -2,44 +2,118 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use skulpin::{CoordinateSystem, RendererBuilder, PresentMode};
 use skulpin::skia_safe::icu;
-use skulpin::winit::dpi::LogicalSize;
-use skulpin::winit::event::{ElementState, Event, MouseScrollDelta, StartCause, WindowEvent};
+use skulpin::winit::dpi::{LogicalSize, PhysicalSize};
+use skulpin::winit::event::{Event, MouseScrollDelta, StartCause, WindowEvent};
 use skulpin::winit::event_loop::{ControlFlow, EventLoop};
-use skulpin::winit::window::WindowBuilder;
+use skulpin::winit::window::{Window, WindowBuilder};
 use neovim_lib::{Neovim, NeovimApi};
+use rmpv::Value;
 use crate::editor::Editor;
-use crate::keybindings::construct_keybinding_string;
+use crate::file_drop::open_dropped_files;
+use crate::keybindings::KeyboardHandler;
+use crate::mouse::MouseHandler;
 use crate::renderer::Renderer;
+use crate::settings::SETTINGS;
+use crate::window_mode::{WindowModeController, fullscreen_key};
+use crate::window_state::{WindowState, record_window_state, restore_window_state, save_window_state};
 
 const EXTRA_LIVE_FRAMES: usize = 10;
+// Focus changes are only reported to neovim once they have settled for this long, so that
+// alt-tabbing through the window does not trigger a burst of autocommands.
+const FOCUS_DEBOUNCE: Duration = Duration::from_millis(100);
 
-pub fn ui_loop(editor: Arc<Mutex<Editor>>, nvim: Neovim, initial_size: (u64, u64)) {
+// g:neovide_scale_factor, falling back to the scale restored from the last session
+fn user_scale_factor(default: f64) -> f64 {
+    SETTINGS.get_f32("scale_factor", default as f32).max(0.1) as f64
+}
+
+// g:neovide_title_format may contain {title} for the title neovim sets through 'titlestring',
+// {icon} for 'iconstring' and {cwd} for the current directory
+fn format_title(title: &str, icon: &str) -> String {
+    let title = if title.is_empty() { "Neovide" } else { title };
+    match SETTINGS.get_string("title_format") {
+        Some(format) => format
+            .replace("{title}", title)
+            .replace("{icon}", icon)
+            .replace("{cwd}", &SETTINGS.get_string("cwd").unwrap_or_default()),
+        None => title.to_string()
+    }
+}
+
+fn resize_grid(nvim: &mut Neovim, renderer: &Renderer, physical_size: PhysicalSize) {
+    if physical_size.width > 0.0 && physical_size.height > 0.0 {
+        let new_width = (physical_size.width as f32 / renderer.font_width) as u64;
+        let new_height = (physical_size.height as f32 / renderer.font_height) as u64;
+        nvim.ui_try_resize((new_width as i64).max(10), (new_height as i64).max(3)).expect("Resize failed");
+    }
+}
+
+fn record_state(window: &Window, window_mode: &WindowModeController, default_scale_factor: f64) {
+    // Fullscreen geometry is not worth restoring, the windowed geometry from before is kept
+    if !window_mode.mode().fullscreen {
+        record_window_state(window, user_scale_factor(default_scale_factor));
+    }
+}
+
+// Window flags from the command line take precedence over the user's config, which in turn takes
+// precedence over the saved state
+pub fn ui_loop(editor: Arc<Mutex<Editor>>, nvim: Neovim, initial_size: (u64, u64), saved_state: Option<WindowState>, window_flags: Vec<&'static str>) {
     let mut nvim = nvim;
-    let mut renderer = Renderer::new(editor.clone());
     let event_loop = EventLoop::<()>::with_user_event();
 
+    // The command line flags and the saved state are applied to the window and the renderer
+    // directly rather than written to the settings
+    let mut saved_state = saved_state;
+    let mut restored_window_modes = Vec::new();
+    let mut default_scale_factor = 1.0;
+    if let Some(state) = saved_state.as_mut() {
+        state.discard_offscreen_position(event_loop.available_monitors());
+        default_scale_factor = state.scale_factor;
+        if state.maximized && SETTINGS.get("maximized").is_none() {
+            restored_window_modes.push(("maximized", true));
+        }
+    }
+
+    let initial_hidpi_factor = event_loop.primary_monitor().hidpi_factor();
+    let mut renderer = Renderer::new(editor.clone(), initial_hidpi_factor * user_scale_factor(default_scale_factor));
+
     let (width, height) = initial_size;
-    let logical_size = LogicalSize::new(
-        (width as f32 * renderer.font_width) as f64, 
-        (height as f32 * renderer.font_height + 1.0) as f64
-    );
+    let logical_size = match &saved_state {
+        Some(state) => state.size,
+        None => LogicalSize::new(
+            (width as f32 * renderer.font_width) as f64 / initial_hidpi_factor,
+            (height as f32 * renderer.font_height) as f64 / initial_hidpi_factor
+        )
+    };
 
-    let window = Arc::new(WindowBuilder::new()
+    let (window_builder, mut window_mode) = WindowModeController::configure(WindowBuilder::new()
         .with_title("Neovide")
-        .with_inner_size(logical_size)
+        .with_inner_size(logical_size), window_flags, restored_window_modes);
+    let window = Arc::new(window_builder
         .build(&event_loop)
         .expect("Failed to create window"));
+    if let Some(state) = saved_state {
+        if let Some(position) = state.position {
+            window.set_outer_position(position);
+        }
+        restore_window_state(state);
+    }
+    record_state(&window, &window_mode, default_scale_factor);
 
     let mut skulpin_renderer = RendererBuilder::new()
         .prefer_integrated_gpu()
         .use_vulkan_debug_layer(true)
         .present_mode_priority(vec![PresentMode::Mailbox, PresentMode::Immediate])
-        .coordinate_system(CoordinateSystem::Logical)
+        .coordinate_system(CoordinateSystem::Physical)
         .build(&window)
         .expect("Failed to create renderer");
 
-    let mut mouse_down = false;
-    let mut mouse_pos = (0, 0);
+    let mut keyboard_handler = KeyboardHandler::new();
+    let mut mouse_handler = MouseHandler::new();
+    let mut dropped_files = Vec::new();
+    let mut reported_focus = true;
+    let mut pending_focus: Option<(bool, Instant)> = None;
+    let mut current_title = "Neovide".to_string();
 
     icu::init();
 
@@ -60,18 +134,40 @@ pub fn ui_loop(editor: Arc<Mutex<Editor>>, nvim: Neovim, initial_size: (u64, u64
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
-            } => *control_flow = ControlFlow::Exit,
+            } => {
+                save_window_state();
+                *control_flow = ControlFlow::Exit
+            },
+
+            Event::WindowEvent {
+                event: WindowEvent::Moved(_),
+                ..
+            } => record_state(&window, &window_mode, default_scale_factor),
 
             Event::WindowEvent {
                 event: WindowEvent::Resized(new_size),
                 ..
             } => {
-                if new_size.width > 0.0 && new_size.height > 0.0 {
-                    let new_width = ((new_size.width + 1.0) as f32 / renderer.font_width) as u64;
-                    let new_height = ((new_size.height + 1.0) as f32 / renderer.font_height) as u64;
-                    // Add 1 here to make sure resizing doesn't change the grid size on startup
-                    nvim.ui_try_resize((new_width as i64).max(10), (new_height as i64).max(3)).expect("Resize failed");
-                }
+                resize_grid(&mut nvim, &renderer, new_size.to_physical(window.hidpi_factor()));
+                record_state(&window, &window_mode, default_scale_factor);
+            },
+
+            Event::WindowEvent {
+                event: WindowEvent::HiDpiFactorChanged(hidpi_factor),
+                ..
+            } => {
+                renderer.set_scale_factor(hidpi_factor * user_scale_factor(default_scale_factor));
+                resize_grid(&mut nvim, &renderer, window.inner_size().to_physical(hidpi_factor));
+                window.request_redraw();
+            },
+
+            Event::WindowEvent {
+                event: WindowEvent::Focused(focused),
+                ..
+            } => {
+                editor.lock().unwrap().focused = focused;
+                pending_focus = Some((focused, Instant::now()));
+                window.request_redraw();
             },
 
             Event::WindowEvent {
@@ -81,79 +177,125 @@ pub fn ui_loop(editor: Arc<Mutex<Editor>>, nvim: Neovim, initial_size: (u64, u64
                 },
                 ..
             } => {
-                if let Some(string) = construct_keybinding_string(input) {
+                for string in keyboard_handler.handle_keyboard_input(input) {
+                    if Some(&string) == fullscreen_key().as_ref() {
+                        let fullscreen = window_mode.toggle_fullscreen();
+                        SETTINGS.set_in_nvim(&mut nvim, "fullscreen", Value::from(fullscreen));
+                        window.request_redraw();
+                    } else {
+                        nvim.input(&string).expect("Input call failed...");
+                    }
+                }
+            },
+
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter(character),
+                ..
+            } => {
+                if let Some(string) = keyboard_handler.handle_received_character(character) {
                     nvim.input(&string).expect("Input call failed...");
                 }
             },
 
+            Event::EventsCleared => {
+                if let Some(string) = keyboard_handler.flush_pending_chord() {
+                    nvim.input(&string).expect("Input call failed...");
+                }
+
+                // Each dropped file arrives as its own event, so they are opened together once
+                // the whole drop has been delivered
+                if !dropped_files.is_empty() {
+                    open_dropped_files(&mut nvim, &dropped_files);
+                    dropped_files.clear();
+                }
+
+                // Sent as keys like the TUI does, so that they are handled between commands
+                // instead of interrupting them
+                if let Some((focused, changed_at)) = pending_focus {
+                    if changed_at.elapsed() >= FOCUS_DEBOUNCE {
+                        pending_focus = None;
+                        if focused != reported_focus {
+                            reported_focus = focused;
+                            let key = if focused { "<FocusGained>" } else { "<FocusLost>" };
+                            nvim.input(key).expect("Could not send focus change");
+                        }
+                    }
+                }
+            },
+
+            Event::WindowEvent {
+                event: WindowEvent::HoveredFile(_),
+                ..
+            } => {
+                editor.lock().unwrap().file_hovering = true;
+                window.request_redraw();
+            },
+
+            Event::WindowEvent {
+                event: WindowEvent::HoveredFileCancelled,
+                ..
+            } => {
+                editor.lock().unwrap().file_hovering = false;
+                window.request_redraw();
+            },
+
+            Event::WindowEvent {
+                event: WindowEvent::DroppedFile(path),
+                ..
+            } => {
+                editor.lock().unwrap().file_hovering = false;
+                dropped_files.push(path);
+                window.request_redraw();
+            },
+
             Event::WindowEvent {
                 event: WindowEvent::CursorMoved {
                     position,
+                    modifiers,
                     ..
                 },
                 ..
             } => {
+                let position = position.to_physical(window.hidpi_factor());
                 let grid_x = (position.x as f32 / renderer.font_width) as i64;
                 let grid_y = (position.y as f32 / renderer.font_height) as i64;
-                mouse_pos = (grid_x, grid_y);
-                if mouse_down {
-                    nvim.input_mouse("left", "drag", "", 0, grid_y, grid_x).expect("Could not send mouse input");
-                }
+                mouse_handler.handle_cursor_moved(&mut nvim, (grid_x, grid_y), modifiers);
             }
 
             Event::WindowEvent {
                 event: WindowEvent::MouseInput {
                     state,
+                    button,
+                    modifiers,
                     ..
                 },
                 ..
             } => {
-                let input_type = match state {
-                    ElementState::Pressed => {
-                        mouse_down = true;
-                        "press"
-                    },
-                    ElementState::Released => {
-                        mouse_down = false;
-                        "release"
-                    }
-                };
-                let (grid_x, grid_y) = mouse_pos;
-                nvim.input_mouse("left", input_type, "", 0, grid_y, grid_x).expect("Could not send mouse input");
+                mouse_handler.handle_mouse_input(&mut nvim, state, button, modifiers);
             }
 
             Event::WindowEvent {
                 event: WindowEvent::MouseWheel {
                     delta: MouseScrollDelta::LineDelta(horizontal, vertical),
+                    modifiers,
                     ..
                 },
                 ..
             } => {
-                let vertical_input_type = if vertical > 0.0 {
-                    Some("up")
-                } else if vertical < 0.0 {
-                    Some("down")
-                } else {
-                    None
-                };
-
-                if let Some(input_type) = vertical_input_type {
-                    let (grid_x, grid_y) = mouse_pos;
-                    nvim.input_mouse("wheel", input_type, "", 0, grid_y, grid_x).expect("Could not send mouse input");
-                }
-
-                let horizontal_input_type = if horizontal > 0.0 {
-                    Some("right")
-                } else if horizontal < 0.0 {
-                    Some("left")
-                } else {
-                    None
-                };
+                mouse_handler.handle_scroll(&mut nvim, (horizontal, vertical), modifiers);
+            }
 
-                if let Some(input_type) = horizontal_input_type {
-                    let (grid_x, grid_y) = mouse_pos;
-                    nvim.input_mouse("wheel", input_type, "", 0, grid_y, grid_x).expect("Could not send mouse input");
-                }
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel {
+                    delta: MouseScrollDelta::PixelDelta(delta),
+                    modifiers,
+                    ..
+                },
+                ..
+            } => {
+                let delta = delta.to_physical(window.hidpi_factor());
+                let lines = (delta.x as f32 / renderer.font_width, delta.y as f32 / renderer.font_height);
+                mouse_handler.handle_scroll(&mut nvim, lines, modifiers);
             }
 
             Event::WindowEvent {
@@ -161,8 +303,38 @@ pub fn ui_loop(editor: Arc<Mutex<Editor>>, nvim: Neovim, initial_size: (u64, u64
                 ..
             } => {
                 frame_start = Instant::now();
+
+                // Window modes are changed through settings, which request a redraw when they
+                // change. Resizing the grid right away keeps it consistent while the window
+                // manager catches up, and the following Resized event corrects it if needed.
+                if window_mode.update(&window) {
+                    resize_grid(&mut nvim, &renderer, window.inner_size().to_physical(window.hidpi_factor()));
+                    record_state(&window, &window_mode, default_scale_factor);
+                }
+
+                // winit windows are updated from the event loop rather than from the thread
+                // handling neovim's events
+                let title = {
+                    let editor = editor.lock().unwrap();
+                    format_title(&editor.title, &editor.icon)
+                };
+                if title != current_title {
+                    window.set_title(&title);
+                    current_title = title;
+                }
+
+                // The user scale factor can change at runtime through g:neovide_scale_factor
+                let hidpi_factor = window.hidpi_factor();
+                let scale_factor = hidpi_factor * user_scale_factor(default_scale_factor);
+                if (scale_factor - renderer.scale_factor).abs() > std::f64::EPSILON {
+                    renderer.set_scale_factor(scale_factor);
+                    resize_grid(&mut nvim, &renderer, window.inner_size().to_physical(hidpi_factor));
+                    record_state(&window, &window_mode, default_scale_factor);
+                }
+
                 if let Err(e) = skulpin_renderer.draw(&window.clone(), |canvas, coordinate_system_helper| {
-                    if renderer.draw(canvas, coordinate_system_helper) {
+                    let window_size = coordinate_system_helper.window_physical_size();
+                    if renderer.draw(canvas, (window_size.width as f32, window_size.height as f32)) {
                         live_frames = EXTRA_LIVE_FRAMES;
                     } else {
                         if live_frames > 0 {
@@ -170,11 +342,17 @@ pub fn ui_loop(editor: Arc<Mutex<Editor>>, nvim: Neovim, initial_size: (u64, u64
                         }
                     }
 
-                    if live_frames > 0 {
-                        *control_flow = ControlFlow::WaitUntil(frame_start + Duration::from_secs_f32(1.0 / 60.0));
+                    let next_frame = if live_frames > 0 {
+                        Some(frame_start + Duration::from_secs_f32(1.0 / 60.0))
                     } else {
-                        *control_flow = ControlFlow::Wait;
-                    }
+                        None
+                    };
+                    let focus_deadline = pending_focus.map(|(_, changed_at)| changed_at + FOCUS_DEBOUNCE);
+                    *control_flow = match (next_frame, focus_deadline) {
+                        (Some(next_frame), Some(focus_deadline)) => ControlFlow::WaitUntil(next_frame.min(focus_deadline)),
+                        (Some(deadline), None) | (None, Some(deadline)) => ControlFlow::WaitUntil(deadline),
+                        (None, None) => ControlFlow::Wait
+                    };
                 }) {
                     println!("Error during draw: {:?}", e);
                     *control_flow = ControlFlow::Exit
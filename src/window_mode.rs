@@ -0,0 +1,138 @@
+use skulpin::winit::dpi::{LogicalPosition, LogicalSize};
+use skulpin::winit::window::{Fullscreen, Window, WindowBuilder};
+
+use crate::settings::SETTINGS;
+
+const DEFAULT_FULLSCREEN_KEY: &str = "<F11>";
+
+// Read from g:neovide_fullscreen, g:neovide_maximized, g:neovide_frameless and
+// g:neovide_always_on_top, which can also be set with command line flags of the same names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowMode {
+    pub fullscreen: bool,
+    pub maximized: bool,
+    pub frameless: bool,
+    pub always_on_top: bool
+}
+
+impl WindowMode {
+    // Command line flags always win. Restored modes take the place of the setting of the same
+    // name until that setting changes after the given settings generation.
+    fn from_settings(flags: &[&str], restored: &[(&str, bool)], restored_generation: u64) -> WindowMode {
+        let get = |name: &str| {
+            if flags.contains(&name) {
+                return true;
+            }
+            match restored.iter().find(|(restored_name, _)| *restored_name == name) {
+                Some((_, value)) if !SETTINGS.changed_since(restored_generation, &[name]) => *value,
+                _ => SETTINGS.get_bool(name, false)
+            }
+        };
+        WindowMode {
+            fullscreen: get("fullscreen"),
+            maximized: get("maximized"),
+            frameless: get("frameless"),
+            always_on_top: get("always_on_top")
+        }
+    }
+}
+
+// The key notation of the gui keybinding that toggles fullscreen, <F11> unless remapped with
+// g:neovide_fullscreen_key. An empty string disables it.
+pub fn fullscreen_key() -> Option<String> {
+    let key = SETTINGS.get_string("fullscreen_key").unwrap_or(DEFAULT_FULLSCREEN_KEY.to_string());
+    if key.is_empty() {
+        None
+    } else {
+        Some(key)
+    }
+}
+
+// Applies changes to the window mode settings to the window. The windowed size and position
+// are remembered when entering fullscreen so they can be restored afterwards.
+//
+// Modes from the command line flags and restored from the last session are applied locally
+// rather than written to the settings, so that they can't race with the user's config. The flags
+// take precedence over the settings, while restored modes only last until the user changes the
+// matching setting.
+pub struct WindowModeController {
+    applied: WindowMode,
+    flags: Vec<&'static str>,
+    restored: Vec<(&'static str, bool)>,
+    restored_generation: u64,
+    windowed_geometry: Option<(LogicalSize, Option<LogicalPosition>)>
+}
+
+impl WindowModeController {
+    // Everything except fullscreen is set up while building the window. Fullscreen needs the
+    // window to exist so its geometry can be remembered, so it is applied by the first update.
+    pub fn configure(builder: WindowBuilder, flags: Vec<&'static str>, restored: Vec<(&'static str, bool)>) -> (WindowBuilder, WindowModeController) {
+        let restored_generation = SETTINGS.generation();
+        let mode = WindowMode::from_settings(&flags, &restored, restored_generation);
+        let builder = builder
+            .with_maximized(mode.maximized)
+            .with_decorations(!mode.frameless)
+            .with_always_on_top(mode.always_on_top);
+
+        let controller = WindowModeController {
+            applied: WindowMode { fullscreen: false, .. mode },
+            flags,
+            restored,
+            restored_generation,
+            windowed_geometry: None
+        };
+        (builder, controller)
+    }
+
+    // The mode the window should be in, which the next update applies
+    pub fn mode(&self) -> WindowMode {
+        WindowMode::from_settings(&self.flags, &self.restored, self.restored_generation)
+    }
+
+    // The fullscreen key leaves fullscreen even when it was requested on the command line.
+    // Returns the new fullscreen state, which is then written to g:neovide_fullscreen.
+    pub fn toggle_fullscreen(&mut self) -> bool {
+        let fullscreen = !self.mode().fullscreen;
+        self.flags.retain(|flag| *flag != "fullscreen");
+        fullscreen
+    }
+
+    // Returns true when the window mode changed, in which case the grid size has to be sent
+    // to neovim again.
+    pub fn update(&mut self, window: &Window) -> bool {
+        let mode = self.mode();
+        if mode == self.applied {
+            return false;
+        }
+
+        if mode.fullscreen != self.applied.fullscreen {
+            if mode.fullscreen {
+                self.windowed_geometry = Some((window.inner_size(), window.outer_position().ok()));
+                window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+            } else {
+                window.set_fullscreen(None);
+                if let Some((size, position)) = self.windowed_geometry.take() {
+                    window.set_inner_size(size);
+                    if let Some(position) = position {
+                        window.set_outer_position(position);
+                    }
+                }
+            }
+        }
+
+        if mode.maximized != self.applied.maximized {
+            window.set_maximized(mode.maximized);
+        }
+
+        if mode.frameless != self.applied.frameless {
+            window.set_decorations(!mode.frameless);
+        }
+
+        if mode.always_on_top != self.applied.always_on_top {
+            window.set_always_on_top(mode.always_on_top);
+        }
+
+        self.applied = mode;
+        true
+    }
+}
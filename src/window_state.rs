@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+use skulpin::winit::dpi::{LogicalPosition, LogicalSize};
+use skulpin::winit::monitor::MonitorHandle;
+use skulpin::winit::window::Window;
+
+const STATE_FILE_NAME: &str = "window.json";
+// winit doesn't report the work area, so a window is considered maximized when it spans the
+// width of its monitor and nearly all of its height, leaving room for panels and taskbars.
+const MAXIMIZED_HEIGHT_RATIO: f64 = 0.85;
+
+lazy_static! {
+    // The geometry to save on exit. It is updated while the window is resized and moved since
+    // neovim may quit the process before the window gets a chance to close.
+    static ref CURRENT_STATE: Mutex<Option<WindowState>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowState {
+    pub size: LogicalSize,
+    pub position: Option<LogicalPosition>,
+    pub maximized: bool,
+    pub scale_factor: f64
+}
+
+// $XDG_STATE_HOME/neovide on unix, falling back to ~/.local/state/neovide. Other platforms
+// keep it in the local data directory.
+fn state_file_path() -> Option<PathBuf> {
+    let state_dir = if cfg!(unix) && !cfg!(target_os = "macos") {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("state")))
+    } else {
+        dirs::data_local_dir()
+    };
+    state_dir.map(|state_dir| state_dir.join("neovide").join(STATE_FILE_NAME))
+}
+
+impl WindowState {
+    fn to_json(&self) -> Value {
+        json!({
+            "width": self.size.width,
+            "height": self.size.height,
+            "x": self.position.map(|position| position.x),
+            "y": self.position.map(|position| position.y),
+            "maximized": self.maximized,
+            "scale_factor": self.scale_factor
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<WindowState> {
+        let position = match (value["x"].as_f64(), value["y"].as_f64()) {
+            (Some(x), Some(y)) => Some(LogicalPosition::new(x, y)),
+            _ => None
+        };
+
+        Some(WindowState {
+            size: LogicalSize::new(value["width"].as_f64()?, value["height"].as_f64()?),
+            position,
+            maximized: value["maximized"].as_bool().unwrap_or(false),
+            scale_factor: value["scale_factor"].as_f64().unwrap_or(1.0)
+        })
+    }
+
+    // Monitor layouts change between launches, so a position is only kept if the top left
+    // corner of the window still lands on one of the current monitors.
+    pub fn discard_offscreen_position(&mut self, monitors: impl Iterator<Item = MonitorHandle>) {
+        let position = match self.position {
+            Some(position) => position,
+            None => return
+        };
+
+        let visible = monitors.into_iter().any(|monitor| {
+            let hidpi_factor = monitor.hidpi_factor();
+            let origin = monitor.position().to_logical(hidpi_factor);
+            let size = monitor.size().to_logical(hidpi_factor);
+            position.x >= origin.x && position.x < origin.x + size.width &&
+                position.y >= origin.y && position.y < origin.y + size.height
+        });
+
+        if !visible {
+            self.position = None;
+        }
+    }
+}
+
+pub fn load_window_state() -> Option<WindowState> {
+    let path = state_file_path()?;
+    let contents = fs::read_to_string(&path).ok()?;
+    match serde_json::from_str::<Value>(&contents) {
+        Ok(value) => WindowState::from_json(&value),
+        Err(error) => {
+            println!("Could not parse {}: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+fn fills_monitor(window: &Window) -> bool {
+    let monitor = window.current_monitor();
+    let monitor_size = monitor.size().to_logical(monitor.hidpi_factor());
+    let window_size = window.outer_size();
+    window_size.width >= monitor_size.width && window_size.height >= monitor_size.height * MAXIMIZED_HEIGHT_RATIO
+}
+
+// Starts from the saved state so that a window which is maximized as soon as it is created
+// still has its windowed geometry to save
+pub fn restore_window_state(state: WindowState) {
+    *CURRENT_STATE.lock().unwrap() = Some(state);
+}
+
+// Neither g:neovide_maximized nor winit know whether the window manager actually maximized the
+// window, so it is detected from the window filling its monitor. The geometry of a maximized
+// window is the monitor's, so the windowed geometry recorded before is kept for restoring.
+pub fn record_window_state(window: &Window, scale_factor: f64) {
+    let maximized = fills_monitor(window);
+    let mut current_state = CURRENT_STATE.lock().unwrap();
+    if let (true, Some(state)) = (maximized, current_state.as_mut()) {
+        state.maximized = true;
+        state.scale_factor = scale_factor;
+        return;
+    }
+
+    *current_state = Some(WindowState {
+        size: window.inner_size(),
+        position: window.outer_position().ok(),
+        maximized,
+        scale_factor
+    });
+}
+
+pub fn save_window_state() {
+    let state = match CURRENT_STATE.lock().unwrap().clone() {
+        Some(state) => state,
+        None => return
+    };
+    let path = match state_file_path() {
+        Some(path) => path,
+        None => return
+    };
+
+    let result = path.parent()
+        .map(|directory| fs::create_dir_all(directory))
+        .unwrap_or(Ok(()))
+        .and_then(|_| fs::write(&path, state.to_json().to_string()));
+    if let Err(error) = result {
+        println!("Could not save window state to {}: {}", path.display(), error);
+    }
+}